@@ -3,13 +3,13 @@
 use std::{error::Error, os::unix::process::parent_id, path::PathBuf, process::Command};
 use hex::FromHex;
 use serde::{Deserialize, Serialize};
-use serde_json;
 use std::collections::HashMap;
 use std::cmp::Ordering;
 use std::collections::HashSet;
 use std::ops::Index;
 use petgraph::graph::{DiGraph, NodeIndex};
 use petgraph::algo::toposort;
+use crate::fee_rate::FeeRate;
 use crate::mempool_data::{self, MempoolTransaction};
 use crate::runner::bcli;
 
@@ -17,7 +17,9 @@ use crate::runner::bcli;
 struct Vin {
     txinwitness: Option<Vec<String>>,
     vout: Option<u32>,
-    txid: Option<String>
+    txid: Option<String>,
+    //the coinbase input's script, in place of a previous outpoint
+    coinbase: Option<String>
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
@@ -30,7 +32,48 @@ struct Vout {
 pub struct BlockTransaction {
     pub txid: String,
     vin: Vec<Vin>,
-    vout: Vec<Vout>
+    vout: Vec<Vout>,
+    //in BTC, as getblock reports it. None for the coinbase and for sources that only list txids
+    fee: Option<f64>,
+    vsize: Option<u32>
+}
+
+impl BlockTransaction {
+    //a transaction known only by its txid, with no inputs or outputs to match replacements on
+    pub fn from_txid(txid: &str) -> Self {
+        BlockTransaction { txid: txid.to_string(), vin: vec![], vout: vec![], fee: None, vsize: None }
+    }
+
+    //a coinbase known only by its txid
+    pub fn coinbase_from_txid(txid: &str) -> Self {
+        let vin = Vin { txinwitness: None, vout: None, txid: None, coinbase: Some(String::new()) };
+        BlockTransaction { txid: txid.to_string(), vin: vec![vin], vout: vec![], fee: None, vsize: None }
+    }
+
+    pub fn is_coinbase(&self) -> bool {
+        self.vin.iter().any(|vin| vin.coinbase.is_some())
+    }
+
+    //"txid:vout" of each spent output; empty for the coinbase
+    pub fn spent_outpoints(&self) -> impl Iterator<Item = String> + '_ {
+        self.vin.iter().filter_map(|vin| Some(format!("{}:{}", vin.txid.as_ref()?, vin.vout?)))
+    }
+
+    fn fee_rate(&self) -> Option<FeeRate> {
+        Some(FeeRate::from_fee_and_vsize((self.fee? * 100_000_000.0).round() as u64, self.vsize?))
+    }
+}
+
+//lowest fee rate paid by a non-coinbase transaction in the block, as getblockstats reports it. a txn
+//whose fee the source does not give is looked up in the mempool it was mined from, and one in neither
+//is left out. None if no fee is known
+pub fn block_min_fee_rate(block_txns: &[BlockTransaction], mempool_txns: &[MempoolTransaction]) -> Option<FeeRate> {
+    let mempool_fee_rates: HashMap<&String, FeeRate> = mempool_txns.iter().map(|txn| (&txn.txid, txn.fee_rate)).collect();
+
+    block_txns.iter()
+        .filter(|txn| !txn.is_coinbase())
+        .filter_map(|txn| txn.fee_rate().or_else(|| mempool_fee_rates.get(&txn.txid).copied()))
+        .min_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal))
 }
 
 #[derive(Deserialize, Serialize, Debug)]
@@ -38,6 +81,33 @@ struct Block {
     tx: Vec<BlockTransaction>
 }
 
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct BlockHeader {
+    pub hash: String,
+    pub height: u32,
+    //absent for the genesis block
+    pub previousblockhash: Option<String>
+}
+
+//getblock at verbosity 1, which lists txids only
+#[derive(Deserialize, Serialize, Debug)]
+struct BlockTxids {
+    tx: Vec<String>
+}
+
+//a transaction of getblocktemplate, with its fee in sats
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct TemplateTransaction {
+    pub txid: String,
+    pub fee: u64,
+    pub weight: u64
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+struct CoreBlockTemplate {
+    transactions: Vec<TemplateTransaction>
+}
+
 #[derive(Debug, Clone)]
 pub struct BlockMonitor {
     prev_block_height: u32,
@@ -48,7 +118,7 @@ pub struct BlockMonitor {
 
 impl BlockMonitor {
     pub fn get_prev_block_height() -> Result<u32, Box<dyn Error>> {
-        let raw_block_height: Vec<u8> = bcli(&format!("getblockcount")).expect("Error getting previous block height");
+        let raw_block_height: Vec<u8> = bcli("getblockcount").expect("Error getting previous block height");
         let block_height_str = String::from_utf8(raw_block_height).expect("Failed to convert bytes to string");
         let block_height: u32 = block_height_str.trim().parse().unwrap_or_else(|err| {
             println!("Error parsing block height: {}", err);
@@ -66,12 +136,17 @@ impl BlockMonitor {
     }
 
     pub fn get_target_block_txns(target_block_height: u32) -> Result<Vec<BlockTransaction>, Box<dyn Error>> {
-        let mut block_transactions: Vec<BlockTransaction> = Vec::new();
-
         let raw_block_hash: Vec<u8> = bcli(&format!("getblockhash {}", target_block_height)).expect("Error getting block hash");
         let block_hash_str = String::from_utf8(raw_block_hash).expect("Failed to convert bytes to string");
 
-        let raw_block: Vec<u8> = bcli(&format!("getblock {} 2", block_hash_str.trim())).expect("Could not get block");
+        Self::get_block_txns(block_hash_str.trim())
+    }
+
+    //by hash rather than height, so a block replaced in a reorg is not mistaken for its replacement
+    pub fn get_block_txns(block_hash: &str) -> Result<Vec<BlockTransaction>, Box<dyn Error>> {
+        let mut block_transactions: Vec<BlockTransaction> = Vec::new();
+
+        let raw_block: Vec<u8> = bcli(&format!("getblock {} 2", block_hash)).expect("Could not get block");
         let block_str = String::from_utf8(raw_block).expect("Failed to convert bytes to string");
 
         let block_data: Block = serde_json::from_str(&block_str).expect("Could not deserialize block data");
@@ -83,8 +158,30 @@ impl BlockMonitor {
         Ok(block_transactions)
     }
 
+    pub fn get_block_header(block_hash: &str) -> Result<BlockHeader, Box<dyn Error>> {
+        let raw_block_header: Vec<u8> = bcli(&format!("getblockheader {}", block_hash)).expect("Could not get block header");
+        let block_header: BlockHeader = serde_json::from_slice(&raw_block_header)?;
+
+        Ok(block_header)
+    }
+
+    pub fn get_block_txids(block_hash: &str) -> Result<Vec<String>, Box<dyn Error>> {
+        let raw_block: Vec<u8> = bcli(&format!("getblock {} 1", block_hash)).expect("Could not get block");
+        let block: BlockTxids = serde_json::from_slice(&raw_block)?;
+
+        Ok(block.tx)
+    }
+
+    //the block core would mine next, in its selection order and without the coinbase
+    pub fn get_block_template() -> Result<Vec<TemplateTransaction>, Box<dyn Error>> {
+        let raw_block_template: Vec<u8> = bcli(r#"getblocktemplate {"rules":["segwit"]}"#).expect("Error getting block template");
+        let block_template: CoreBlockTemplate = serde_json::from_slice(&raw_block_template)?;
+
+        Ok(block_template.transactions)
+    }
+
     pub fn get_latest_target_block() -> Result<u32, Box<dyn Error>> {
-        let raw_block_count: Vec<u8> = bcli(&format!("getblockcount")).expect("Error getting block count");
+        let raw_block_count: Vec<u8> = bcli("getblockcount").expect("Error getting block count");
         let block_count_str = String::from_utf8(raw_block_count).expect("Failed to convert bytes to string");
 
         let block_count: u32 = block_count_str.trim().parse().unwrap_or_else(| err | {
@@ -98,7 +195,10 @@ impl BlockMonitor {
     }
 }
 
-#[derive(Debug, Clone)]
+//upper bounds (sat/vB) of the buckets of a template's fee rate histogram
+pub const FEE_RATE_HISTOGRAM_BUCKETS: [f64; 9] = [1.0, 2.0, 3.0, 5.0, 10.0, 20.0, 50.0, 100.0, f64::INFINITY];
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct BlockMetrics {
     pub total_txns_included_in_block: u32,
     pub total_txns_in_mempool: u32,
@@ -144,6 +244,64 @@ impl BlockMetrics {
     }
 }
 
+//why the builder put a transaction in the template
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum InclusionReason {
+    //no parent in the mempool, selected in fee rate order while it fit
+    FeeRate,
+    //selected after all its parents in the mempool were included
+    AfterAncestors,
+    //selected although a parent in the mempool did not fit, since the builder does not hold children back
+    WithoutAncestors
+}
+
+//template txns whose fee rate is at least lower and below the next bucket's
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FeeRateBucket {
+    pub lower: FeeRate,
+    pub txns_count: usize,
+    pub weight: u64
+}
+
+//the block the builder would mine from a mempool, in selection order
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct BlockTemplate {
+    pub txns: Vec<MempoolTransaction>,
+    pub metrics: BlockMetrics,
+    //by txid
+    pub inclusion_reasons: HashMap<String, InclusionReason>,
+    pub fee_rate_histogram: Vec<FeeRateBucket>,
+    //None for an empty template
+    pub min_fee_rate: Option<FeeRate>,
+    pub max_fee_rate: Option<FeeRate>
+}
+
+impl BlockTemplate {
+    fn new(txns: Vec<MempoolTransaction>, metrics: BlockMetrics, inclusion_reasons: HashMap<String, InclusionReason>) -> Self {
+        let mut fee_rate_histogram: Vec<FeeRateBucket> = FEE_RATE_HISTOGRAM_BUCKETS.iter().enumerate().map(|(i, _)| FeeRateBucket {
+            lower: FeeRate::from_sat_per_vb(if i == 0 { 0.0 } else { FEE_RATE_HISTOGRAM_BUCKETS[i - 1] }),
+            txns_count: 0,
+            weight: 0
+        }).collect();
+
+        for txn in txns.iter() {
+            let bucket = FEE_RATE_HISTOGRAM_BUCKETS.iter().position(|upper| txn.fee_rate.sat_per_vb() < *upper).unwrap_or(FEE_RATE_HISTOGRAM_BUCKETS.len() - 1);
+            fee_rate_histogram[bucket].txns_count += 1;
+            fee_rate_histogram[bucket].weight += txn.weight;
+        }
+
+        let fee_rates = txns.iter().map(|txn| txn.fee_rate).filter(|fee_rate| !fee_rate.sat_per_vb().is_nan());
+        let min_fee_rate = fee_rates.clone().min_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+        let max_fee_rate = fee_rates.max_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+
+        BlockTemplate { txns, metrics, inclusion_reasons, fee_rate_histogram, min_fee_rate, max_fee_rate }
+    }
+}
+
+//the txns selected for a template along with why each was selected, by txid
+pub type SelectedTxns = (Vec<MempoolTransaction>, HashMap<String, InclusionReason>);
+
 pub struct BlockBuilder {
     mempool_txns_graph: DiGraph<MempoolTransaction, ()>,
     txns: Vec<MempoolTransaction>,
@@ -152,7 +310,7 @@ pub struct BlockBuilder {
 
 impl BlockBuilder {
 
-    pub fn build_block(mempool_txns: &Vec<MempoolTransaction>) -> Result<Vec<MempoolTransaction>, Box<dyn Error>> {
+    pub fn build_block(mempool_txns: &[MempoolTransaction]) -> Result<BlockTemplate, Box<dyn Error>> {
         
         let mut block_metrics = BlockMetrics {
             total_txns_included_in_block: 0,
@@ -165,22 +323,22 @@ impl BlockBuilder {
     
         let mut block_builder: BlockBuilder = BlockBuilder {
             mempool_txns_graph: DiGraph::new(),
-            txns: mempool_txns.clone(),
+            txns: mempool_txns.to_vec(),
             block_metrics: block_metrics.clone()
         };
 
         //calculate total possible fees to measure fee maximization
-        let total_possible_fees = block_metrics.calculate_total_possible_fees(mempool_txns.clone()).expect("Oops!");
+        let total_possible_fees = block_metrics.calculate_total_possible_fees(mempool_txns.to_vec()).expect("Oops!");
         block_metrics.total_possible_fees = total_possible_fees;
 
-        //sort transactions by transaction value (fee_rate) - Fee / Weight Unit - to maximize fees
+        //sort transactions by transaction value (modified fee rate) - Fee / Virtual Byte - to maximize fees
         let _ = BlockBuilder::sort_txns_by_fee_rate(&mut block_builder);
 
         //sort transactions in topological order
         let sorted_txns = BlockBuilder::sort_txns_by_ancestor_dependencies(&mut block_builder).expect("Error Fetching Sorted Txns");
         
         //select transactions to be included in block without exceeding max block weight
-        let block_txns = BlockBuilder::select_txns_to_be_included_in_block(&mut block_builder, sorted_txns).expect("Error Fetching Block Txns");
+        let (block_txns, inclusion_reasons) = BlockBuilder::select_txns_to_be_included_in_block(&mut block_builder, sorted_txns).expect("Error Fetching Block Txns");
         block_metrics.total_txns_included_in_block = block_txns.len() as u32;
 
         //calculate total fees
@@ -194,16 +352,17 @@ impl BlockBuilder {
         let total_weight = block_metrics.calculate_total_block_weight(block_txns.clone()).expect("Error Calculating Total Block Weight");
         block_metrics.total_weight = total_weight;
         
-        Ok(block_txns)
+        Ok(BlockTemplate::new(block_txns, block_metrics, inclusion_reasons))
     }
 
-    //this prioritizes txns based on fee_rate (fee / weight). Fee rate tells us the value / unit of data of each txn
+    //this prioritizes txns based on modified fee rate (fee / vsize, including prioritisetransaction deltas). Fee rate tells us the value / unit of data of each txn
     pub fn sort_txns_by_fee_rate(block_builder: &mut BlockBuilder) -> Result<(), Box<dyn Error>> {
         block_builder.txns.sort_by(| a, b | {
-            if a.fee_rate.is_nan() || b.fee_rate.is_nan() {
+            let (a_fee_rate, b_fee_rate) = (a.modified_fee_rate(), b.modified_fee_rate());
+            if a_fee_rate.is_nan() || b_fee_rate.is_nan() {
                 Ordering::Equal
             } else {
-                a.fee_rate.partial_cmp(&b.fee_rate).expect("Could not sort by fee_rate")
+                a_fee_rate.partial_cmp(&b_fee_rate).expect("Could not sort by fee_rate")
             }
         });
         Ok(())
@@ -238,17 +397,72 @@ impl BlockBuilder {
         Ok(sorted_txns)
     }
 
-    pub fn select_txns_to_be_included_in_block(block_builder: &mut BlockBuilder, sorted_txns: Vec<MempoolTransaction>) -> Result<Vec<MempoolTransaction>, Box<dyn Error>> {
+    pub fn select_txns_to_be_included_in_block(block_builder: &mut BlockBuilder, sorted_txns: Vec<MempoolTransaction>) -> Result<SelectedTxns, Box<dyn Error>> {
         let mut current_block_weight = block_builder.block_metrics.total_weight;
         let max_block_weight = 4_000_000;
         let mut block_txns: Vec<MempoolTransaction> = vec![];
+        let mut inclusion_reasons: HashMap<String, InclusionReason> = HashMap::new();
+
+        let mempool_txids: HashSet<&String> = block_builder.txns.iter().map(|txn| &txn.txid).collect();
 
         for txn in sorted_txns.iter() {
             if current_block_weight + txn.weight <= max_block_weight {
+                let mut mempool_parent_txids = txn.parent_txids.iter().filter(|parent_txid| mempool_txids.contains(parent_txid)).peekable();
+
+                let reason = if mempool_parent_txids.peek().is_none() {
+                    InclusionReason::FeeRate
+                } else if mempool_parent_txids.all(|parent_txid| inclusion_reasons.contains_key(parent_txid)) {
+                    InclusionReason::AfterAncestors
+                } else {
+                    InclusionReason::WithoutAncestors
+                };
+
+                inclusion_reasons.insert(txn.txid.clone(), reason);
                 block_txns.push(txn.clone());
                 current_block_weight += txn.weight;
             }
         }
-        Ok(block_txns)
+        Ok((block_txns, inclusion_reasons))
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_why_each_txn_was_selected() {
+        let mempool_txns = vec![
+            MempoolTransaction::for_test("parent", 1000, 100, &[]),
+            MempoolTransaction::for_test("child", 5000, 100, &["parent"]),
+            //too heavy for any block, so its child follows without it
+            MempoolTransaction::for_test("heavy", 1000, 1_000_001, &[]),
+            MempoolTransaction::for_test("orphan", 1000, 100, &["heavy"]),
+            MempoolTransaction::for_test("confirmed-child", 1000, 100, &["not-in-mempool"]),
+        ];
+
+        let template = BlockBuilder::build_block(&mempool_txns).unwrap();
+
+        assert_eq!(template.txns.len(), 4);
+        assert_eq!(template.inclusion_reasons.len(), 4);
+        assert_eq!(template.inclusion_reasons["parent"], InclusionReason::FeeRate);
+        assert_eq!(template.inclusion_reasons["child"], InclusionReason::AfterAncestors);
+        assert_eq!(template.inclusion_reasons["orphan"], InclusionReason::WithoutAncestors);
+        assert_eq!(template.inclusion_reasons["confirmed-child"], InclusionReason::FeeRate);
+        assert!(!template.inclusion_reasons.contains_key("heavy"));
+    }
+
+    #[test]
+    fn block_min_fee_rate_skips_the_coinbase_and_unknown_fees() {
+        let block_txns: Vec<BlockTransaction> = serde_json::from_str(r#"[
+            {"txid": "coinbase", "vin": [{"coinbase": "03a0bb0d"}], "vout": []},
+            {"txid": "from-getblock", "vin": [], "vout": [], "fee": 0.00000500, "vsize": 100},
+            {"txid": "from-mempool", "vin": [], "vout": []},
+            {"txid": "unknown", "vin": [], "vout": []}
+        ]"#).unwrap();
+        let mempool_txns = vec![MempoolTransaction::for_test("from-mempool", 300, 100, &[])];
+
+        assert_eq!(block_min_fee_rate(&block_txns, &mempool_txns), Some(FeeRate::from_sat_per_vb(3.0)));
+        assert_eq!(block_min_fee_rate(&block_txns, &[]), Some(FeeRate::from_sat_per_vb(5.0)));
+        assert_eq!(block_min_fee_rate(&block_txns[..1], &mempool_txns), None);
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,85 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use crate::block_data::{BlockTemplate, BlockTransaction};
+use crate::mempool_data::MempoolTransaction;
+
+//how closely the template built from the pre-block snapshot predicted the block that was mined. fees
+//and weights of mined txns are only known for those in the snapshot mempool
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct BlockFidelity {
+    pub template_txns_count: usize,
+    //excluding the coinbase
+    pub block_txns_count: usize,
+    pub mined_txns_not_in_mempool_count: usize,
+    pub template_txns_mined_count: usize,
+    pub template_txns_left_out_count: usize,
+    pub template_fees: u64,
+    pub template_weight: u64,
+    pub mined_txns_fees: u64,
+    pub mined_txns_weight: u64,
+    //fees and weight of the mined txns over those of the template
+    pub fee_captured: f64,
+    pub weight_used: f64,
+}
+
+impl BlockFidelity {
+    pub fn measure(template: &BlockTemplate, mempool_txns: &[MempoolTransaction], block_txns: &[BlockTransaction]) -> Self {
+        //the coinbase is never in the mempool
+        let block_txids: HashSet<&String> = block_txns.iter().filter(|txn| !txn.is_coinbase()).map(|txn| &txn.txid).collect();
+        let mempool_txids: HashSet<&String> = mempool_txns.iter().map(|txn| &txn.txid).collect();
+
+        let mined_txns: Vec<&MempoolTransaction> = mempool_txns.iter().filter(|txn| block_txids.contains(&txn.txid)).collect();
+        let template_txns = &template.txns;
+        let template_txns_mined_count = template_txns.iter().filter(|txn| block_txids.contains(&txn.txid)).count();
+
+        let template_fees = template.metrics.total_fees;
+        let template_weight = template.metrics.total_weight;
+        let mined_txns_fees: u64 = mined_txns.iter().map(|txn| txn.fee).sum();
+        let mined_txns_weight: u64 = mined_txns.iter().map(|txn| txn.weight).sum();
+
+        BlockFidelity {
+            template_txns_count: template_txns.len(),
+            block_txns_count: block_txids.len(),
+            mined_txns_not_in_mempool_count: block_txids.iter().filter(|txid| !mempool_txids.contains(*txid)).count(),
+            template_txns_mined_count,
+            template_txns_left_out_count: template_txns.len() - template_txns_mined_count,
+            template_fees,
+            template_weight,
+            mined_txns_fees,
+            mined_txns_weight,
+            fee_captured: mined_txns_fees as f64 / template_fees as f64,
+            weight_used: mined_txns_weight as f64 / template_weight as f64,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block_data::BlockBuilder;
+
+    #[test]
+    fn coinbase_is_told_by_its_input_not_its_position() {
+        let mempool_txns = vec![
+            MempoolTransaction::for_test("mined", 1000, 100, &[]),
+            MempoolTransaction::for_test("left-out", 2000, 100, &[]),
+        ];
+        let template = BlockBuilder::build_block(&mempool_txns).unwrap();
+
+        //as getblock verbosity 2 lists them, but with the coinbase out of its usual place
+        let block_txns: Vec<BlockTransaction> = serde_json::from_str(r#"[
+            {"txid": "mined", "vin": [{"txid": "confirmed", "vout": 0}], "vout": []},
+            {"txid": "coinbase", "vin": [{"coinbase": "03a0bb0d"}], "vout": [{"n": 0, "value": 3.125}]},
+            {"txid": "unseen", "vin": [{"txid": "confirmed", "vout": 1}], "vout": []}
+        ]"#).unwrap();
+
+        let fidelity = BlockFidelity::measure(&template, &mempool_txns, &block_txns);
+
+        assert_eq!(fidelity.block_txns_count, 2);
+        assert_eq!(fidelity.mined_txns_not_in_mempool_count, 1);
+        assert_eq!(fidelity.template_txns_mined_count, 1);
+        assert_eq!(fidelity.template_txns_left_out_count, 1);
+        assert_eq!(fidelity.mined_txns_fees, 1000);
+        assert_eq!(fidelity.fee_captured, 1000.0 / 3000.0);
+    }
+}
@@ -0,0 +1,324 @@
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::time::Instant;
+use crate::block_data::{BlockHeader, BlockMonitor, BlockTransaction};
+
+//how many of the most recent blocks are remembered to find where a reorg forked off
+const TRACKED_BLOCKS_COUNT: u32 = 100;
+
+//where the analyzer learns about blocks. bitcoind in a run, a scripted chain to exercise reorgs
+pub trait ChainSource: Send + Sync {
+    fn tip_height(&self) -> Result<u32, Box<dyn Error + Send + Sync>>;
+    fn block_hash(&self, block_height: u32) -> Result<String, Box<dyn Error + Send + Sync>>;
+    fn block_header(&self, block_hash: &str) -> Result<BlockHeader, Box<dyn Error + Send + Sync>>;
+    fn block_txns(&self, block_hash: &str) -> Result<Vec<BlockTransaction>, Box<dyn Error + Send + Sync>>;
+}
+
+pub struct BitcoindChainSource;
+
+impl ChainSource for BitcoindChainSource {
+    fn tip_height(&self) -> Result<u32, Box<dyn Error + Send + Sync>> {
+        BlockMonitor::get_prev_block_height().map_err(|e| e.to_string().into())
+    }
+
+    fn block_hash(&self, block_height: u32) -> Result<String, Box<dyn Error + Send + Sync>> {
+        BlockMonitor::get_block_hash(block_height).map_err(|e| e.to_string().into())
+    }
+
+    fn block_header(&self, block_hash: &str) -> Result<BlockHeader, Box<dyn Error + Send + Sync>> {
+        BlockMonitor::get_block_header(block_hash).map_err(|e| e.to_string().into())
+    }
+
+    fn block_txns(&self, block_hash: &str) -> Result<Vec<BlockTransaction>, Box<dyn Error + Send + Sync>> {
+        BlockMonitor::get_block_txns(block_hash).map_err(|e| e.to_string().into())
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct ScriptedBlock {
+    hash: String,
+    height: u32,
+    previousblockhash: Option<String>,
+    #[serde(default)]
+    txids: Vec<String>,
+    #[serde(default)]
+    coinbase_txid: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ScriptedTip {
+    //seconds since the run started from which this block is the tip
+    after_secs: u64,
+    hash: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct ChainScript {
+    blocks: Vec<ScriptedBlock>,
+    tips: Vec<ScriptedTip>,
+}
+
+//replays a chain from a json file of blocks and the tip over time, e.g. a block at height 101
+//replaced by a competing 101 and 102 a minute later, so reorg handling can be tried without a node
+pub struct ScriptedChainSource {
+    blocks: HashMap<String, ScriptedBlock>,
+    tips: Vec<ScriptedTip>,
+    started_at: Instant,
+}
+
+impl ScriptedChainSource {
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let script: ChainScript = serde_json::from_str(&fs::read_to_string(path)?)?;
+
+        if script.tips.is_empty() {
+            return Err(format!("{} has no tips", path.display()).into());
+        }
+
+        for tip in &script.tips {
+            if !script.blocks.iter().any(|block| block.hash == tip.hash) {
+                return Err(format!("Tip {} is not one of the blocks in {}", tip.hash, path.display()).into());
+            }
+        }
+
+        Ok(ScriptedChainSource {
+            blocks: script.blocks.into_iter().map(|block| (block.hash.clone(), block)).collect(),
+            tips: script.tips,
+            started_at: Instant::now(),
+        })
+    }
+
+    fn tip(&self) -> &ScriptedBlock {
+        let elapsed_secs = self.started_at.elapsed().as_secs();
+        let tip = self.tips.iter()
+            .take_while(|tip| tip.after_secs <= elapsed_secs)
+            .last()
+            .unwrap_or(&self.tips[0]);
+
+        &self.blocks[&tip.hash]
+    }
+
+    fn block(&self, block_hash: &str) -> Result<&ScriptedBlock, Box<dyn Error + Send + Sync>> {
+        self.blocks.get(block_hash).ok_or_else(|| format!("Block {} is not in the chain script", block_hash).into())
+    }
+}
+
+impl ChainSource for ScriptedChainSource {
+    fn tip_height(&self) -> Result<u32, Box<dyn Error + Send + Sync>> {
+        Ok(self.tip().height)
+    }
+
+    //walks back from the current tip, as the active chain may have changed since the last call
+    fn block_hash(&self, block_height: u32) -> Result<String, Box<dyn Error + Send + Sync>> {
+        let mut block = self.tip();
+
+        while block.height > block_height {
+            let prev_hash = block.previousblockhash.as_deref().ok_or("Chain script ends before the requested height")?;
+            block = self.block(prev_hash)?;
+        }
+
+        if block.height == block_height {
+            Ok(block.hash.clone())
+        } else {
+            Err(format!("No block at height {} in the chain script", block_height).into())
+        }
+    }
+
+    fn block_header(&self, block_hash: &str) -> Result<BlockHeader, Box<dyn Error + Send + Sync>> {
+        let block = self.block(block_hash)?;
+
+        Ok(BlockHeader {
+            hash: block.hash.clone(),
+            height: block.height,
+            previousblockhash: block.previousblockhash.clone(),
+        })
+    }
+
+    fn block_txns(&self, block_hash: &str) -> Result<Vec<BlockTransaction>, Box<dyn Error + Send + Sync>> {
+        let block = self.block(block_hash)?;
+
+        Ok(block.coinbase_txid.iter().map(|txid| BlockTransaction::coinbase_from_txid(txid))
+            .chain(block.txids.iter().map(|txid| BlockTransaction::from_txid(txid)))
+            .collect())
+    }
+}
+
+//a block as identified by its hash, with the height it was at when seen
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlockRef {
+    pub height: u32,
+    pub hash: String,
+}
+
+//how the best chain moved since it was last looked at. disconnected is empty unless there was a
+//reorg, or the tip went stale and was rolled back, and both are in height order
+#[derive(Debug, Default)]
+pub struct ChainUpdate {
+    pub disconnected: Vec<BlockRef>,
+    pub connected: Vec<BlockRef>,
+}
+
+//the recent best chain as last seen, followed by hash and previous block hash so a replaced
+//block is noticed even when the height does not change
+#[derive(Debug)]
+pub struct ChainTracker {
+    blocks: BTreeMap<u32, String>,
+}
+
+impl ChainTracker {
+    pub fn new(chain_source: &dyn ChainSource) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let tip_height = chain_source.tip_height()?;
+        let tip_hash = chain_source.block_hash(tip_height)?;
+
+        Ok(ChainTracker { blocks: BTreeMap::from([(tip_height, tip_hash)]) })
+    }
+
+    pub fn tip_height(&self) -> u32 {
+        *self.blocks.keys().next_back().expect("Chain tracker is never empty")
+    }
+
+    pub fn update(&mut self, chain_source: &dyn ChainSource) -> Result<ChainUpdate, Box<dyn Error + Send + Sync>> {
+        let tip_height = chain_source.tip_height()?;
+        let mut block_hash = chain_source.block_hash(tip_height)?;
+        let mut block_height = tip_height;
+
+        //follow previous block hashes back from the new tip until reaching a block already on the tracked chain
+        let lowest_tracked_height = *self.blocks.keys().next().expect("Chain tracker is never empty");
+        let mut connected = vec![];
+
+        while self.blocks.get(&block_height) != Some(&block_hash) && block_height >= lowest_tracked_height {
+            let header = chain_source.block_header(&block_hash)?;
+            connected.push(BlockRef { height: block_height, hash: block_hash });
+
+            match header.previousblockhash {
+                Some(prev_hash) => {
+                    block_hash = prev_hash;
+                    block_height -= 1;
+                }
+                None => break,
+            }
+        }
+
+        connected.reverse();
+
+        let disconnected: Vec<BlockRef> = self.blocks
+            .split_off(&(block_height + 1))
+            .into_iter()
+            .map(|(height, hash)| BlockRef { height, hash })
+            .collect();
+
+        for block in &connected {
+            self.blocks.insert(block.height, block.hash.clone());
+        }
+
+        //the reorg went deeper than the tracked blocks, so the tracked chain starts over from the new tip
+        if self.blocks.is_empty() {
+            self.blocks.insert(tip_height, chain_source.block_hash(tip_height)?);
+        }
+
+        while self.blocks.len() > TRACKED_BLOCKS_COUNT as usize {
+            self.blocks.pop_first();
+        }
+
+        Ok(ChainUpdate { disconnected, connected })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //blocks named by branch and height, e.g. a100, with the branch forking off a at fork_height
+    fn branch(name: &str, fork_height: u32, tip_height: u32) -> Vec<ScriptedBlock> {
+        (fork_height + 1..=tip_height).map(|height| ScriptedBlock {
+            hash: format!("{}{}", name, height),
+            height,
+            previousblockhash: match height {
+                0 => None,
+                _ if height == fork_height + 1 => Some(format!("a{}", fork_height)),
+                _ => Some(format!("{}{}", name, height - 1)),
+            },
+            txids: vec![],
+            coinbase_txid: None,
+        }).collect()
+    }
+
+    //the scripted chain with the tip fixed at one block
+    fn chain_at(blocks: &[Vec<ScriptedBlock>], tip: &str) -> ScriptedChainSource {
+        let blocks: HashMap<String, ScriptedBlock> = blocks.iter().flatten().map(|block| (block.hash.clone(), block.clone())).collect();
+        assert!(blocks.contains_key(tip));
+
+        ScriptedChainSource {
+            blocks,
+            tips: vec![ScriptedTip { after_secs: 0, hash: tip.to_string() }],
+            started_at: Instant::now(),
+        }
+    }
+
+    fn block_refs(branch: &str, heights: std::ops::RangeInclusive<u32>) -> Vec<BlockRef> {
+        heights.map(|height| BlockRef { height, hash: format!("{}{}", branch, height) }).collect()
+    }
+
+    #[test]
+    fn replaced_tip_is_disconnected() {
+        let blocks = [branch("a", 0, 101), branch("b", 100, 101)];
+        let mut tracker = ChainTracker::new(&chain_at(&blocks, "a101")).unwrap();
+
+        let update = tracker.update(&chain_at(&blocks, "b101")).unwrap();
+
+        assert_eq!(update.disconnected, block_refs("a", 101..=101));
+        assert_eq!(update.connected, block_refs("b", 101..=101));
+        assert_eq!(tracker.tip_height(), 101);
+    }
+
+    #[test]
+    fn rolled_back_tip_is_disconnected() {
+        let blocks = [branch("a", 0, 101)];
+        let mut tracker = ChainTracker::new(&chain_at(&blocks, "a101")).unwrap();
+
+        let update = tracker.update(&chain_at(&blocks, "a100")).unwrap();
+
+        assert_eq!(update.disconnected, block_refs("a", 101..=101));
+        assert!(update.connected.is_empty());
+        assert_eq!(tracker.tip_height(), 100);
+
+        let update = tracker.update(&chain_at(&blocks, "a101")).unwrap();
+
+        assert!(update.disconnected.is_empty());
+        assert_eq!(update.connected, block_refs("a", 101..=101));
+    }
+
+    #[test]
+    fn every_block_of_a_jump_is_connected() {
+        let blocks = [branch("a", 0, 103)];
+        let mut tracker = ChainTracker::new(&chain_at(&blocks, "a100")).unwrap();
+
+        assert!(tracker.update(&chain_at(&blocks, "a100")).unwrap().connected.is_empty());
+
+        let update = tracker.update(&chain_at(&blocks, "a103")).unwrap();
+
+        assert!(update.disconnected.is_empty());
+        assert_eq!(update.connected, block_refs("a", 101..=103));
+        assert_eq!(tracker.tip_height(), 103);
+    }
+
+    #[test]
+    fn reorg_deeper_than_the_tracked_blocks_starts_over() {
+        let blocks = [branch("a", 0, 150), branch("b", 10, 160)];
+        let mut tracker = ChainTracker::new(&chain_at(&blocks, "a50")).unwrap();
+        tracker.update(&chain_at(&blocks, "a150")).unwrap();
+
+        let update = tracker.update(&chain_at(&blocks, "b160")).unwrap();
+
+        //only the tracked blocks are known to be disconnected, and the new chain is followed back as far as them
+        assert_eq!(update.disconnected, block_refs("a", 51..=150));
+        assert_eq!(update.connected, block_refs("b", 51..=160));
+        assert_eq!(tracker.tip_height(), 160);
+        assert_eq!(tracker.blocks.len(), TRACKED_BLOCKS_COUNT as usize);
+
+        let update = tracker.update(&chain_at(&blocks, "b160")).unwrap();
+        assert!(update.disconnected.is_empty() && update.connected.is_empty());
+    }
+}
@@ -1,7 +1,16 @@
-use clap::{Parser, Subcommand, ValueEnum};
+use clap::error::ErrorKind;
+use clap::{Args, CommandFactory, Parser, Subcommand, ValueEnum};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use crate::fee_rate::FeeRateUnit;
+use crate::filter_expression::FilterExpression;
+use crate::fee_rate::FeeRate;
+use crate::mempool_data_subsets::{ThresholdSource, VSIZE_BUCKETS};
+use crate::strategies::STRATEGY_NAMES;
+use crate::txn_shape::ScriptType;
 
-#[derive(Parser, Debug, Clone)]
-#[command(version, about, long_about = None)]
+#[derive(Parser, Serialize, Deserialize, Debug, Clone)]
+#[command(version, about, long_about = None, after_help = "To continue an interrupted run: mempool-fee-analyzer resume <RUN_DIR>")]
 pub struct Config {
     #[arg(short, long, help = "Name of the fee estimation strategy to use", value_name = "STRATEGY_NAME")]
     pub strategy_name: String,
@@ -9,15 +18,138 @@ pub struct Config {
     #[arg(short, long, help = "How many blocks in the future do you want to run this strategy for?", value_name = "DURATION")]
     pub duration: usize,
 
+    #[arg(long, help = "Show an interactive terminal dashboard instead of plain progress output")]
+    pub tui: bool,
+
+    #[arg(short, long, default_value = ".", help = "Directory in which a folder is created for each run's results", value_name = "OUTPUT_DIR")]
+    pub output_dir: PathBuf,
+
+    #[arg(short, long, value_enum, default_value_t = FeeRateUnit::SatPerVb, help = "Unit of the fee rates written to the results")]
+    #[serde(default)]
+    pub units: FeeRateUnit,
+
+    #[arg(long, help = "Decode every mempool transaction to classify its script types and shape. Implied by the shape subset options")]
+    #[serde(default)]
+    pub decode_txns: bool,
+
+    #[arg(long, help = "Count subset transactions whose replacement was mined as included after a fee bump. Implies --decode-txns")]
+    #[serde(default)]
+    pub track_replacements: bool,
+
+    #[arg(long, value_delimiter = ',', default_values_t = default_fee_band_edges(), help = "Comma-separated fee rates (sat/vB) at which the snapshot mempool is split into bands for the inclusion curve", value_name = "SAT_PER_VB")]
+    #[serde(default = "default_fee_band_edges")]
+    pub fee_bands: Vec<f64>,
+
+    #[arg(long = "zmq", help = "Bitcoin Core ZMQ endpoint publishing hashblock, rawtx and sequence, e.g. tcp://127.0.0.1:28332. Repeat for topics published on separate endpoints. Switches from polling to an event-driven mempool", value_name = "ENDPOINT")]
+    #[serde(default)]
+    pub zmq_endpoints: Vec<String>,
+
+    #[arg(long, help = "Read the mempool and blocks from an Esplora REST API at this base URL instead of bitcoind, e.g. https://blockstream.info/api", value_name = "URL")]
+    #[serde(default)]
+    pub esplora: Option<String>,
+
+    #[arg(long, help = "Record mempool.space's recommended fees and projected blocks at each snapshot next to our block template, from the API at this base URL, e.g. https://mempool.space/api", value_name = "URL")]
+    #[serde(default)]
+    pub mempool_space: Option<String>,
+
+    #[arg(long, help = "Diff our block template against bitcoind's getblocktemplate at each snapshot")]
+    #[serde(default)]
+    pub compare_block_template: bool,
+
+    #[arg(long, default_value_t = default_compare_interval(), help = "Seconds between the --mempool-space and --compare-block-template comparisons, each against the latest snapshot. 0 compares every snapshot", value_name = "SECONDS")]
+    #[serde(default = "default_compare_interval")]
+    pub compare_interval: u64,
+
+    #[arg(long, hide = true, help = "Replay blocks from a JSON chain script instead of asking bitcoind, to exercise reorg handling", value_name = "FILE")]
+    #[serde(default)]
+    pub chain_script: Option<PathBuf>,
+
     #[clap(subcommand)]
     pub commands: Commands,
 }
 
-#[derive(ValueEnum, Clone, Debug)]
+fn default_fee_band_edges() -> Vec<f64> {
+    vec![1.0, 2.0, 5.0, 10.0, 20.0, 50.0, 100.0]
+}
+
+fn default_compare_interval() -> u64 {
+    30
+}
+
+impl Config {
+    //the default subset from the command line options first, then any from --subsets
+    pub fn subsets(&self) -> Vec<SubsetDefinition> {
+        match &self.commands {
+            Commands::SubsetSample { filters, subsets } => {
+                let default_subset = SubsetDefinition { name: DEFAULT_SUBSET_NAME.to_string(), filters: filters.clone() };
+                std::iter::once(default_subset).chain(subsets.iter().flat_map(|s| s.0.iter().cloned())).collect()
+            }
+        }
+    }
+
+    //(lower, upper) sat/vB of each band, from 0 up to the first edge and from the last edge up without bound
+    pub fn fee_bands(&self) -> Vec<(FeeRate, FeeRate)> {
+        let mut edges: Vec<f64> = self.fee_bands.iter().copied().filter(|edge| *edge > 0.0).collect();
+        edges.sort_by(|a, b| a.total_cmp(b));
+        edges.dedup();
+
+        let lower_bounds = std::iter::once(0.0).chain(edges.iter().copied());
+        let upper_bounds = edges.iter().copied().chain(std::iter::once(f64::INFINITY));
+
+        lower_bounds.zip(upper_bounds)
+            .map(|(lower, upper)| (FeeRate::from_sat_per_vb(lower), FeeRate::from_sat_per_vb(upper)))
+            .collect()
+    }
+
+    //decoding costs a bitcoin-cli call per new mempool transaction, so it is skipped unless something uses it
+    pub fn needs_txn_shapes(&self) -> bool {
+        self.decode_txns || self.track_replacements || self.subsets().iter().any(|subset| subset.filters.needs_txn_shapes())
+    }
+    pub fn uses_prev_block_min_fee_rate(&self) -> bool {
+        self.subsets().iter().any(|subset| subset.filters.uses_prev_block_min_fee_rate())
+    }
+
+    pub fn compares_templates(&self) -> bool {
+        self.mempool_space.is_some() || self.compare_block_template
+    }
+
+    //with no interval every snapshot is compared as it is taken, e.g. against a local mock
+    pub fn compares_every_snapshot(&self) -> bool {
+        self.compares_templates() && self.compare_interval == 0
+    }
+
+    //combinations clap cannot express, as the shape filters may come from the --subsets file
+    pub fn validate(&self) -> Result<(), String> {
+        //esplora lists no raw transactions to decode
+        if self.esplora.is_some() && self.needs_txn_shapes() {
+            return Err("--esplora cannot be used with --decode-txns, --track-replacements or the shape subset options, which decode transactions with bitcoind".to_string());
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(ValueEnum, Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub enum Comparison {
     LessThan,
     GreaterThan,
     Equals,
+    LessThanOrEquals,
+    GreaterThanOrEquals,
+    NotEquals,
+}
+
+impl Comparison {
+    pub fn compare<T: PartialOrd>(&self, left: T, right: T) -> bool {
+        match self {
+            Comparison::LessThan => left < right,
+            Comparison::GreaterThan => left > right,
+            Comparison::Equals => left == right,
+            Comparison::LessThanOrEquals => left <= right,
+            Comparison::GreaterThanOrEquals => left >= right,
+            Comparison::NotEquals => left != right,
+        }
+    }
 }
 
 fn parse_comparison_and_count(s: &str) -> Result<(Comparison, u32), String> {
@@ -38,25 +170,260 @@ fn parse_comparison_and_count(s: &str) -> Result<(Comparison, u32), String> {
     Ok((comparison, count))
 }
 
-#[derive(Subcommand, Debug, Clone)]
+//a single count or an inclusive range such as 2-5
+fn parse_count_range(s: &str) -> Result<(u32, u32), String> {
+    let invalid = || "Invalid range. Use '<count>' or '<min>-<max>' (e.g., 2-5).".to_string();
+
+    let (min, max) = match s.split_once('-') {
+        Some((min, max)) => (min.trim().parse::<u32>().map_err(|_| invalid())?, max.trim().parse::<u32>().map_err(|_| invalid())?),
+        None => {
+            let count = s.trim().parse::<u32>().map_err(|_| invalid())?;
+            (count, count)
+        }
+    };
+
+    if min > max {
+        return Err(invalid());
+    }
+
+    Ok((min, max))
+}
+
+fn parse_threshold_source(s: &str) -> Result<ThresholdSource, String> {
+    let invalid = || "Invalid threshold. Use strategy, strategy:<name>, fixed:<sat/vB>, percentile:<0-100> or prev-block-min.".to_string();
+
+    match s.split_once(':') {
+        None if s == "strategy" => Ok(ThresholdSource::Strategy),
+        None if s == "prev-block-min" => Ok(ThresholdSource::PrevBlockMinFeeRate),
+        Some(("strategy", name)) if STRATEGY_NAMES.contains(&name) => Ok(ThresholdSource::OtherStrategy(name.to_string())),
+        Some(("strategy", name)) => Err(format!("Unknown strategy '{}'. Use one of {}.", name, STRATEGY_NAMES.join(", "))),
+        Some(("fixed", value)) => {
+            let sat_per_vb = value.parse::<f64>().map_err(|_| invalid())?;
+            Ok(ThresholdSource::Fixed(FeeRate::from_sat_per_vb(sat_per_vb)))
+        }
+        Some(("percentile", value)) => {
+            let percentile = value.parse::<f64>().map_err(|_| invalid())?;
+            if !(0.0..=100.0).contains(&percentile) {
+                return Err(invalid());
+            }
+            Ok(ThresholdSource::MempoolPercentile(percentile))
+        }
+        _ => Err(invalid()),
+    }
+}
+
+fn validate_vsize_bucket(s: &str) -> Result<String, String> {
+    if VSIZE_BUCKETS.iter().any(|(_, label)| *label == s) {
+        Ok(s.to_string())
+    } else {
+        let labels: Vec<&str> = VSIZE_BUCKETS.iter().map(|(_, label)| *label).collect();
+        Err(format!("Invalid vsize bucket. Use one of {}.", labels.join(", ")))
+    }
+}
+
+//the filters selecting one mempool subset; the same options define the subsets in a --subsets file
+#[derive(Args, Serialize, Deserialize, Debug, Clone, Default)]
+pub struct SubsetFilters {
+    #[clap(group = "fee_tier")]
+    #[clap(long, help = "Select low-fee transactions.")]
+    pub low_fee_txns: bool,
+
+    #[clap(group = "fee_tier")]
+    #[clap(long, help = "Select high-fee transactions.")]
+    pub high_fee_txns: bool,
+
+    #[clap(long, default_value = "strategy", help = "Threshold between low- and high-fee transactions: strategy (the evaluated strategy's estimate), strategy:<name>, fixed:<sat/vB>, percentile:<0-100> of the mempool, or prev-block-min.", value_parser = parse_threshold_source)]
+    #[serde(default)]
+    pub fee_threshold: ThresholdSource,
+
+    #[clap(long, help = "Select transactions with inputs <comparison> <count>.", value_parser = parse_comparison_and_count)]
+    pub inputs: Option<(Comparison, u32)>,
+
+    #[clap(long, help = "Select transactions with outputs <comparison> <count>.", value_parser = parse_comparison_and_count)]
+    pub outputs: Option<(Comparison, u32)>,
+
+    #[clap(long, help = "Select transactions that have been in the mempool for <comparison> <seconds>.", value_parser = parse_comparison_and_count)]
+    pub age: Option<(Comparison, u32)>,
+
+    #[clap(long, help = "Select transactions in a vsize bucket, e.g. '200-500vB'.", value_parser = validate_vsize_bucket)]
+    pub vsize_bucket: Option<String>,
+
+    #[clap(long, value_enum, help = "Select transactions whose inputs all spend this script type, or 'mixed' for several types.")]
+    pub input_script_type: Option<ScriptType>,
+
+    #[clap(long, value_enum, help = "Select transactions with an output of this script type, or 'mixed' for several types.")]
+    pub output_script_type: Option<ScriptType>,
+
+    #[clap(long, help = "Select transactions with unconfirmed ancestors.")]
+    #[serde(default)]
+    pub has_unconfirmed_ancestors: bool,
+
+    #[clap(long, help = "Select CPFP children: transactions paying a higher fee rate than their unconfirmed ancestors.")]
+    #[serde(default)]
+    pub cpfp_child: bool,
+
+    #[clap(long, help = "Select transactions whose ancestor count (including themselves) is <count> or in <min>-<max>.", value_parser = parse_count_range)]
+    pub ancestor_count: Option<(u32, u32)>,
+
+    #[clap(group = "rbf")]
+    #[clap(long, help = "Select transactions signaling BIP125 replaceability.")]
+    #[serde(default)]
+    pub rbf_signaling: bool,
+
+    #[clap(group = "rbf")]
+    #[clap(long, help = "Select transactions not signaling BIP125 replaceability.")]
+    #[serde(default)]
+    pub no_rbf: bool,
+
+    #[clap(long, help = "Select transactions with an OP_RETURN output.")]
+    #[serde(default)]
+    pub op_return: bool,
+
+    #[clap(long, help = "Select transactions carrying inscription-like witness data.")]
+    #[serde(default)]
+    pub inscription: bool,
+
+    #[clap(long, help = "Select transactions matching a filter expression, e.g. 'fee_rate > 20 and vsize < 300 and (rbf or ancestors > 0)'.", value_parser = FilterExpression::parse)]
+    pub filter: Option<FilterExpression>,
+}
+
+impl SubsetFilters {
+    pub fn uses_prev_block_min_fee_rate(&self) -> bool {
+        (self.low_fee_txns || self.high_fee_txns) && self.fee_threshold == ThresholdSource::PrevBlockMinFeeRate
+    }
+
+    //fields that are only known once the raw transactions are decoded
+    pub fn needs_txn_shapes(&self) -> bool {
+        self.inputs.is_some()
+            || self.outputs.is_some()
+            || self.input_script_type.is_some()
+            || self.output_script_type.is_some()
+            || self.op_return
+            || self.inscription
+            || self.filter.as_ref().is_some_and(|f| f.referenced_fields().iter().any(|field| field.needs_shape()))
+    }
+}
+
+//the subset selected by the options given on the command line
+pub const DEFAULT_SUBSET_NAME: &str = "default";
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SubsetDefinition {
+    pub name: String,
+    pub filters: SubsetFilters,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(transparent)]
+pub struct SubsetDefinitions(pub Vec<SubsetDefinition>);
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct SubsetFileEntry {
+    name: String,
+    #[serde(default)]
+    args: Vec<String>,
+}
+
+#[derive(Parser)]
+struct SubsetArgs {
+    #[command(flatten)]
+    filters: SubsetFilters,
+}
+
+//the file lists named subsets with the same options as the command line, e.g.
+//[{"name": "small_low_fee", "args": ["--low-fee-txns", "--vsize-bucket", "<200vB"]}, {"name": "all", "args": []}].
+//it is read once and stored in the config so a resumed run evaluates the same subsets
+fn load_subsets_file(path: &str) -> Result<SubsetDefinitions, String> {
+    let data = std::fs::read_to_string(path).map_err(|e| format!("Could not read subsets file: {}", e))?;
+    let entries: Vec<SubsetFileEntry> = serde_json::from_str(&data).map_err(|e| format!("Invalid subsets file: {}", e))?;
+
+    let mut definitions: Vec<SubsetDefinition> = vec![];
+    for entry in entries {
+        if entry.name == DEFAULT_SUBSET_NAME || definitions.iter().any(|d| d.name == entry.name) {
+            return Err(format!("Subset name '{}' is reserved or used more than once", entry.name));
+        }
+
+        let subset_args = SubsetArgs::try_parse_from(std::iter::once(entry.name.clone()).chain(entry.args))
+            .map_err(|e| format!("Invalid options for subset '{}': {}", entry.name, e))?;
+
+        definitions.push(SubsetDefinition { name: entry.name, filters: subset_args.filters });
+    }
+
+    Ok(SubsetDefinitions(definitions))
+}
+
+#[derive(Subcommand, Serialize, Deserialize, Debug, Clone)]
 pub enum Commands {
     SubsetSample {
-        #[clap(group = "fee_tier")]
-        #[clap(long, help = "Select low-fee transactions.")]
-        low_fee_txns: bool,
+        #[command(flatten)]
+        #[serde(flatten)]
+        filters: SubsetFilters,
+
+        #[clap(long, help = "JSON file of additional named subsets, each evaluated against the same snapshots and blocks as the default one.", value_name = "FILE", value_parser = load_subsets_file)]
+        #[serde(default)]
+        subsets: Option<SubsetDefinitions>,
+    }
+}
 
-        #[clap(group = "fee_tier")]
-        #[clap(long, help = "Select high-fee transactions.")]
-        high_fee_txns: bool,
+#[derive(Parser, Debug, Clone)]
+#[command(bin_name = "mempool-fee-analyzer resume", version, about = "Resume an interrupted analysis run", long_about = None)]
+pub struct ResumeConfig {
+    #[arg(help = "Folder of the run to resume, as created under --output-dir", value_name = "RUN_DIR")]
+    pub run_dir: PathBuf,
 
-        #[clap(long, help = "Select transactions with inputs <comparison> <count>.", value_parser = parse_comparison_and_count)]
-        inputs: Option<(Comparison, u32)>,
+    #[arg(long, help = "Show an interactive terminal dashboard instead of plain progress output")]
+    pub tui: bool,
+}
 
-        #[clap(long, help = "Select transactions with outputs <comparison> <count>.", value_parser = parse_comparison_and_count)]
-        outputs: Option<(Comparison, u32)>,
-    }
+pub enum Invocation {
+    Run(Box<Config>),
+    Resume(ResumeConfig),
 }
 
 pub fn parse_config() -> Config {
-    Config::parse()
-}
\ No newline at end of file
+    let config = Config::parse();
+
+    if let Err(message) = config.validate() {
+        Config::command().error(ErrorKind::ArgumentConflict, message).exit();
+    }
+
+    config
+}
+
+//resume takes its strategy, duration and filters from the run's manifest,
+//so it is parsed on its own instead of as a subcommand that would require them
+pub fn parse_invocation() -> Invocation {
+    if std::env::args_os().nth(1).is_some_and(|arg| arg == "resume") {
+        Invocation::Resume(ResumeConfig::parse_from(std::env::args_os().skip(1)))
+    } else {
+        Invocation::Run(Box::new(parse_config()))
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config_from(args: &[&str]) -> Config {
+        let base_args = ["mempool-fee-analyzer", "--strategy-name", "block_template_median", "--duration", "1"];
+        Config::try_parse_from(base_args.iter().chain(args)).expect("valid arguments")
+    }
+
+    #[test]
+    fn esplora_is_rejected_with_txn_decoding() {
+        assert!(config_from(&["--esplora", "http://127.0.0.1:3000", "subset-sample"]).validate().is_ok());
+        assert!(config_from(&["--esplora", "http://127.0.0.1:3000", "--decode-txns", "subset-sample"]).validate().is_err());
+        assert!(config_from(&["--esplora", "http://127.0.0.1:3000", "--track-replacements", "subset-sample"]).validate().is_err());
+        assert!(config_from(&["--esplora", "http://127.0.0.1:3000", "subset-sample", "--filter", "op_return"]).validate().is_err());
+        assert!(config_from(&["--decode-txns", "subset-sample"]).validate().is_ok());
+    }
+
+    #[test]
+    fn compare_interval_of_zero_compares_every_snapshot() {
+        let config = config_from(&["--compare-block-template", "subset-sample"]);
+        assert_eq!(config.compare_interval, 30);
+        assert!(config.compares_templates() && !config.compares_every_snapshot());
+
+        assert!(config_from(&["--mempool-space", "http://127.0.0.1:3000", "--compare-interval", "0", "subset-sample"]).compares_every_snapshot());
+        assert!(!config_from(&["--compare-interval", "0", "subset-sample"]).compares_every_snapshot());
+    }
+}
@@ -0,0 +1,264 @@
+use std::error::Error;
+use std::time::Duration;
+use ratatui::DefaultTerminal;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Direction, Layout, Rect};
+use ratatui::style::{Color, Style};
+use ratatui::symbols;
+use ratatui::text::Line;
+use ratatui::widgets::{Axis, BarChart, Block, Borders, Chart, Dataset, Gauge, GraphType, Paragraph, Row, Table};
+use ratatui::Frame;
+use crate::config::{Config, DEFAULT_SUBSET_NAME};
+use crate::fee_rate::FeeRateUnit;
+use std::path::Path;
+use crate::result::{AnalyzerResult, AnalyzerResultProcessor, ResultStore};
+use crate::runner::SnapshotData;
+
+//one per FEE_RATE_HISTOGRAM_BUCKETS
+const HISTOGRAM_LABELS: [&str; 9] = ["<1", "1-2", "2-3", "3-5", "5-10", "10-20", "20-50", "50-100", "100+"];
+
+//number of recent result rows kept in the rolling chart
+const RECENT_RESULTS_WINDOW: usize = 50;
+
+//values derived from a snapshot, recomputed only when the runner takes a new snapshot
+#[derive(Default)]
+struct DashboardView {
+    units: FeeRateUnit,
+    snapshot_timestamp: String,
+    mempool_txns_count: usize,
+    mempool_weight: u64,
+    target_block_height: u32,
+    template_histogram: Vec<u64>,
+    //the strategy's estimate, then each subset's, as the analysis computed them for the snapshot
+    estimates: Vec<(String, f64)>,
+    blocks_found_count: usize,
+    recent_results: Vec<AnalyzerResult>,
+}
+
+pub struct Dashboard {
+    terminal: DefaultTerminal,
+    strategy_name: String,
+    duration: usize,
+    units: FeeRateUnit,
+    result_store: ResultStore,
+    view: DashboardView,
+}
+
+impl Dashboard {
+    pub fn new(config: &Config, run_dir: &Path, run_id: &str) -> Result<Self, Box<dyn Error>> {
+        let result_store = ResultStore::open(run_dir, run_id).map_err(|e| e.to_string())?;
+        let terminal = ratatui::try_init()?;
+
+        Ok(Dashboard {
+            terminal,
+            strategy_name: config.strategy_name.clone(),
+            duration: config.duration,
+            units: config.units,
+            result_store,
+            view: DashboardView::default(),
+        })
+    }
+
+    pub fn draw(&mut self, snapshot: &SnapshotData) -> Result<(), Box<dyn Error>> {
+        let new_block_found = snapshot.analyzer_result().blocks_found_count != self.view.blocks_found_count;
+
+        if snapshot.analyzer_result().snapshot_timestamp != self.view.snapshot_timestamp || new_block_found {
+            self.refresh_view(snapshot);
+        }
+
+        //spawned analysis tasks print progress lines when a block is found, so repaint from scratch
+        if new_block_found {
+            self.terminal.clear()?;
+        }
+
+        let strategy_name = &self.strategy_name;
+        let duration = self.duration;
+        let view = &self.view;
+        self.terminal.draw(|frame| render(frame, strategy_name, duration, view))?;
+
+        Ok(())
+    }
+
+    //returns true when the user pressed q or ctrl-c (raw mode swallows SIGINT while the dashboard is up)
+    pub fn quit_requested(&self) -> Result<bool, Box<dyn Error>> {
+        while event::poll(Duration::ZERO)? {
+            if let Event::Key(key) = event::read()? {
+                if key.kind != KeyEventKind::Press {
+                    continue;
+                }
+
+                let ctrl_c = key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL);
+                if key.code == KeyCode::Char('q') || key.code == KeyCode::Esc || ctrl_c {
+                    return Ok(true);
+                }
+            }
+        }
+
+        Ok(false)
+    }
+
+    fn refresh_view(&mut self, snapshot: &SnapshotData) {
+        let mempool_txns = &snapshot.mempool_txns;
+
+        let template_histogram = snapshot.template.fee_rate_histogram.iter().map(|bucket| bucket.txns_count as u64).collect();
+
+        let mut estimates = vec![(self.strategy_name.clone(), snapshot.analyzer_result().mempool_fee_rate_estimate.in_units(self.units))];
+        estimates.extend(snapshot.analyzer_results.iter().map(|result| {
+            (format!("{} subset", result.subset_name), result.mempool_subset_fee_rate_estimate.in_units(self.units))
+        }));
+
+        let mut recent_results = self.view.recent_results.clone();
+        if snapshot.analyzer_result().blocks_found_count != self.view.blocks_found_count && self.result_store.result_exists() {
+            if let Ok(result) = self.result_store.load_intermediate_result() {
+                //the chart follows the default subset
+                let result: Vec<AnalyzerResult> = result.into_iter().filter(|r| r.subset_name == DEFAULT_SUBSET_NAME && !r.reorged).collect();
+                let skip = result.len().saturating_sub(RECENT_RESULTS_WINDOW);
+                recent_results = result.into_iter().skip(skip).collect();
+            }
+        }
+
+        self.view = DashboardView {
+            units: self.units,
+            snapshot_timestamp: snapshot.analyzer_result().snapshot_timestamp.clone(),
+            mempool_txns_count: mempool_txns.len(),
+            mempool_weight: mempool_txns.iter().map(|txn| txn.weight).sum(),
+            target_block_height: snapshot.target_block_height,
+            template_histogram,
+            estimates,
+            blocks_found_count: snapshot.analyzer_result().blocks_found_count,
+            recent_results,
+        };
+    }
+}
+
+impl Drop for Dashboard {
+    fn drop(&mut self) {
+        ratatui::restore();
+    }
+}
+
+fn render(frame: &mut Frame, strategy_name: &str, duration: usize, view: &DashboardView) {
+    let rows = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Length(3),
+            Constraint::Length(7),
+            Constraint::Min(10),
+            Constraint::Min(10),
+        ])
+        .split(frame.area());
+
+    render_progress(frame, rows[0], strategy_name, duration, view);
+
+    let middle = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
+        .split(rows[1]);
+
+    render_mempool(frame, middle[0], view);
+    render_estimates(frame, middle[1], view);
+    render_template_histogram(frame, rows[2], view);
+    render_recent_results(frame, rows[3], view);
+}
+
+fn render_progress(frame: &mut Frame, area: Rect, strategy_name: &str, duration: usize, view: &DashboardView) {
+    let ratio = if duration == 0 {
+        1.0
+    } else {
+        (view.blocks_found_count as f64 / duration as f64).min(1.0)
+    };
+
+    let gauge = Gauge::default()
+        .block(Block::default().borders(Borders::ALL).title(format!(" {} - press q to quit ", strategy_name)))
+        .gauge_style(Style::default().fg(Color::Green))
+        .ratio(ratio)
+        .label(format!("{} / {} blocks found", view.blocks_found_count, duration));
+
+    frame.render_widget(gauge, area);
+}
+
+fn render_mempool(frame: &mut Frame, area: Rect, view: &DashboardView) {
+    let lines = vec![
+        Line::from(format!("Transactions:  {}", view.mempool_txns_count)),
+        Line::from(format!("Weight:        {} WU", view.mempool_weight)),
+        Line::from(format!("Blocks worth:  {:.2}", view.mempool_weight as f64 / 4_000_000.0)),
+        Line::from(format!("Target block:  {}", view.target_block_height)),
+        Line::from(format!("Snapshot:      {}", view.snapshot_timestamp)),
+    ];
+
+    let paragraph = Paragraph::new(lines)
+        .block(Block::default().borders(Borders::ALL).title(" Mempool "));
+
+    frame.render_widget(paragraph, area);
+}
+
+fn render_estimates(frame: &mut Frame, area: Rect, view: &DashboardView) {
+    let rows: Vec<Row> = view.estimates.iter().map(|(name, estimate)| {
+        Row::new(vec![name.clone(), format!("{:.*}", view.units.decimals(), estimate)])
+    }).collect();
+
+    let table = Table::new(rows, [Constraint::Percentage(70), Constraint::Percentage(30)])
+        .header(Row::new(vec!["Strategy", view.units.label()]).style(Style::default().fg(Color::Yellow)))
+        .block(Block::default().borders(Borders::ALL).title(" Current Estimates "));
+
+    frame.render_widget(table, area);
+}
+
+fn render_template_histogram(frame: &mut Frame, area: Rect, view: &DashboardView) {
+    let data: Vec<(&str, u64)> = HISTOGRAM_LABELS.iter().copied().zip(view.template_histogram.iter().copied()).collect();
+
+    let bar_width = (area.width.saturating_sub(2) / HISTOGRAM_LABELS.len() as u16).saturating_sub(1).max(1);
+
+    let bar_chart = BarChart::default()
+        .block(Block::default().borders(Borders::ALL).title(" Projected Block Template - txns by fee rate (sat/vB) "))
+        .data(&data)
+        .bar_width(bar_width)
+        .bar_style(Style::default().fg(Color::Cyan))
+        .value_style(Style::default().fg(Color::Black).bg(Color::Cyan));
+
+    frame.render_widget(bar_chart, area);
+}
+
+fn render_recent_results(frame: &mut Frame, area: Rect, view: &DashboardView) {
+    let estimates: Vec<(f64, f64)> = view.recent_results.iter()
+        .map(|r| (r.target_block_height as f64, r.mempool_fee_rate_estimate.in_units(view.units)))
+        .collect();
+    let probabilities: Vec<(f64, f64)> = view.recent_results.iter()
+        .map(|r| (r.target_block_height as f64, if r.conditional_probability.is_nan() { 0.0 } else { r.conditional_probability * 100.0 }))
+        .collect();
+
+    let min_height = estimates.first().map(|(h, _)| *h).unwrap_or(0.0);
+    let max_height = estimates.last().map(|(h, _)| *h).unwrap_or(1.0).max(min_height + 1.0);
+    let max_y = estimates.iter().map(|(_, e)| *e).fold(100.0, f64::max);
+
+    let datasets = vec![
+        Dataset::default()
+            .name(format!("estimate ({})", view.units.label()))
+            .marker(symbols::Marker::Braille)
+            .graph_type(GraphType::Line)
+            .style(Style::default().fg(Color::Yellow))
+            .data(&estimates),
+        Dataset::default()
+            .name("subset txns in block (%)")
+            .marker(symbols::Marker::Braille)
+            .graph_type(GraphType::Line)
+            .style(Style::default().fg(Color::Magenta))
+            .data(&probabilities),
+    ];
+
+    let chart = Chart::new(datasets)
+        .block(Block::default().borders(Borders::ALL).title(format!(" Recent Results (last {} blocks) ", RECENT_RESULTS_WINDOW)))
+        .x_axis(
+            Axis::default()
+                .title("target block height")
+                .bounds([min_height, max_height])
+                .labels([format!("{}", min_height), format!("{}", max_height)]),
+        )
+        .y_axis(
+            Axis::default()
+                .bounds([0.0, max_y])
+                .labels(["0".to_string(), format!("{:.0}", max_y)]),
+        );
+
+    frame.render_widget(chart, area);
+}
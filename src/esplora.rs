@@ -0,0 +1,278 @@
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use crate::block_data::{BlockHeader, BlockTransaction};
+use crate::chain_source::ChainSource;
+use crate::fee_rate::FeeRate;
+use crate::mempool_data::MempoolTransaction;
+use crate::mempool_state::MempoolSource;
+
+//how often the tracked txns are checked against /mempool/txids, which runs to megabytes, to drop
+//those replaced or evicted. mined txns are dropped as each block's txns are fetched
+const MEMPOOL_RECONCILE_INTERVAL: Duration = Duration::from_secs(600);
+
+//a thin blocking client for the esplora rest api, e.g. https://blockstream.info/api,
+//https://mempool.space/api or a self-hosted instance
+pub struct EsploraClient {
+    base_url: String,
+    agent: ureq::Agent,
+}
+
+impl EsploraClient {
+    pub fn new(base_url: &str) -> Self {
+        EsploraClient {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            agent: ureq::AgentBuilder::new().timeout(Duration::from_secs(30)).build(),
+        }
+    }
+
+    fn get_text(&self, path: &str) -> Result<String, Box<dyn Error + Send + Sync>> {
+        Ok(self.agent.get(&format!("{}{}", self.base_url, path)).call()?.into_string()?)
+    }
+
+    pub fn get_json<T: DeserializeOwned>(&self, path: &str) -> Result<T, Box<dyn Error + Send + Sync>> {
+        Ok(self.agent.get(&format!("{}{}", self.base_url, path)).call()?.into_json()?)
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct EsploraBlock {
+    id: String,
+    height: u32,
+    previousblockhash: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct EsploraMempoolStats {
+    count: usize,
+}
+
+#[derive(Deserialize, Debug)]
+struct EsploraRecentTx {
+    txid: String,
+    fee: u64,
+    vsize: u32,
+}
+
+#[derive(Deserialize, Debug)]
+struct EsploraVin {
+    #[serde(default)]
+    is_coinbase: bool,
+}
+
+#[derive(Deserialize, Debug)]
+struct EsploraTx {
+    txid: String,
+    vin: Vec<EsploraVin>,
+}
+
+//the txns seen in /mempool/recent that have not left the mempool since
+#[derive(Default)]
+struct EsploraMempool {
+    txns: HashMap<String, MempoolTransaction>,
+    last_reconciled: Option<Instant>,
+}
+
+//blocks and mempool from esplora, for when there is no node to ask. fetching each mempool txn takes a
+//request per txn, so the mempool is instead built up from the fee and vsize /mempool/recent lists for
+//the last few txns to arrive, and covers only txns that arrived while the analyzer was running.
+//esplora has no package stats, wtxids or entry times: packages, parents and rbf signaling are left
+//unknown, and a txn's time is when it was first seen in /mempool/recent
+pub struct EsploraSource {
+    client: EsploraClient,
+    mempool: Mutex<EsploraMempool>,
+    reconcile_interval: Duration,
+    tip_height: AtomicU32,
+}
+
+impl EsploraSource {
+    pub fn new(base_url: &str) -> Self {
+        EsploraSource {
+            client: EsploraClient::new(base_url),
+            mempool: Mutex::new(EsploraMempool::default()),
+            reconcile_interval: MEMPOOL_RECONCILE_INTERVAL,
+            tip_height: AtomicU32::new(0),
+        }
+    }
+
+    fn to_mempool_txn(&self, recent_txn: EsploraRecentTx, time: u64) -> MempoolTransaction {
+        MempoolTransaction {
+            fee_rate: FeeRate::from_fee_and_vsize(recent_txn.fee, recent_txn.vsize),
+            parent_txids: vec![],
+            inputs_count: 0,
+            outputs_count: 0,
+            wtxid: None,
+            vsize: recent_txn.vsize,
+            modified_fee: recent_txn.fee,
+            package: None,
+            bip125_replaceable: None,
+            unbroadcast: false,
+            time,
+            height: self.tip_height.load(Ordering::Relaxed),
+            shape: None,
+            txid: recent_txn.txid,
+            fee: recent_txn.fee,
+            weight: recent_txn.vsize as u64 * 4,
+        }
+    }
+
+    //adds the txns in /mempool/recent not seen before, and every so often drops those no longer listed
+    //in /mempool/txids
+    fn update_mempool(&self) -> Result<HashSet<String>, Box<dyn Error + Send + Sync>> {
+        let recent_txns: Vec<EsploraRecentTx> = self.client.get_json("/mempool/recent")?;
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).expect("Time went backwards").as_secs();
+
+        let mut mempool = self.mempool.lock().expect("Esplora mempool lock poisoned");
+
+        for recent_txn in recent_txns {
+            if !mempool.txns.contains_key(&recent_txn.txid) {
+                let txn = self.to_mempool_txn(recent_txn, now);
+                mempool.txns.insert(txn.txid.clone(), txn);
+            }
+        }
+
+        if mempool.last_reconciled.is_none_or(|last_reconciled| last_reconciled.elapsed() >= self.reconcile_interval) {
+            let mempool_txids: HashSet<String> = self.client.get_json("/mempool/txids")?;
+            mempool.txns.retain(|txid, _| mempool_txids.contains(txid));
+            mempool.last_reconciled = Some(Instant::now());
+        }
+
+        Ok(mempool.txns.keys().cloned().collect())
+    }
+}
+
+impl ChainSource for EsploraSource {
+    fn tip_height(&self) -> Result<u32, Box<dyn Error + Send + Sync>> {
+        let tip_height: u32 = self.client.get_text("/blocks/tip/height")?.trim().parse()?;
+        self.tip_height.store(tip_height, Ordering::Relaxed);
+
+        Ok(tip_height)
+    }
+
+    fn block_hash(&self, block_height: u32) -> Result<String, Box<dyn Error + Send + Sync>> {
+        Ok(self.client.get_text(&format!("/block-height/{}", block_height))?.trim().to_string())
+    }
+
+    fn block_header(&self, block_hash: &str) -> Result<BlockHeader, Box<dyn Error + Send + Sync>> {
+        let block: EsploraBlock = self.client.get_json(&format!("/block/{}", block_hash))?;
+
+        Ok(BlockHeader {
+            hash: block.id,
+            height: block.height,
+            previousblockhash: block.previousblockhash,
+        })
+    }
+
+    //txids only, as fetching the inputs too takes a request per 25 txns. replacements are not matched.
+    //the first page of txns is fetched to tell the coinbase by its input. the block's txns are dropped
+    //from the tracked mempool
+    fn block_txns(&self, block_hash: &str) -> Result<Vec<BlockTransaction>, Box<dyn Error + Send + Sync>> {
+        let txids: Vec<String> = self.client.get_json(&format!("/block/{}/txids", block_hash))?;
+        let first_txns: Vec<EsploraTx> = self.client.get_json(&format!("/block/{}/txs/0", block_hash))?;
+
+        let coinbase_txids: HashSet<String> = first_txns.into_iter()
+            .filter(|txn| txn.vin.iter().any(|vin| vin.is_coinbase))
+            .map(|txn| txn.txid)
+            .collect();
+
+        let mut mempool = self.mempool.lock().expect("Esplora mempool lock poisoned");
+        for txid in &txids {
+            mempool.txns.remove(txid);
+        }
+
+        Ok(txids.iter().map(|txid| {
+            if coinbase_txids.contains(txid) {
+                BlockTransaction::coinbase_from_txid(txid)
+            } else {
+                BlockTransaction::from_txid(txid)
+            }
+        }).collect())
+    }
+}
+
+impl MempoolSource for EsploraSource {
+    fn mempool_txns(&self) -> Result<Vec<MempoolTransaction>, Box<dyn Error + Send + Sync>> {
+        self.update_mempool()?;
+        let stats: EsploraMempoolStats = self.client.get_json("/mempool")?;
+
+        let mempool = self.mempool.lock().expect("Esplora mempool lock poisoned");
+        println!("Tracking {} of {} esplora mempool txns, seen in /mempool/recent", mempool.txns.len(), stats.count);
+
+        Ok(mempool.txns.values().cloned().collect())
+    }
+
+    fn mempool_txids(&self) -> Result<HashSet<String>, Box<dyn Error + Send + Sync>> {
+        self.update_mempool()
+    }
+
+    //served from the txns seen in /mempool/recent, without a request
+    fn mempool_entry(&self, txid: &str) -> Option<MempoolTransaction> {
+        self.mempool.lock().expect("Esplora mempool lock poisoned").txns.get(txid).cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::TestServer;
+
+    fn recent_json(txns: &[(&str, u64, u32)]) -> String {
+        let txns: Vec<String> = txns.iter()
+            .map(|(txid, fee, vsize)| format!(r#"{{"txid":"{}","fee":{},"vsize":{},"value":1000}}"#, txid, fee, vsize))
+            .collect();
+        format!("[{}]", txns.join(","))
+    }
+
+    #[test]
+    fn mempool_is_built_up_from_recent_txns_without_fetching_each() {
+        let server = TestServer::start();
+        server.set("/mempool/recent", recent_json(&[("a", 1_000, 100), ("b", 500, 250)]));
+        server.set("/mempool/txids", r#"["a","b","c"]"#);
+        server.set("/mempool", r#"{"count":3}"#);
+
+        let mut source = EsploraSource::new(&server.base_url);
+        let txns = source.mempool_txns().expect("mempool txns");
+        assert_eq!(txns.len(), 2);
+
+        let a = source.mempool_entry("a").expect("a tracked");
+        assert_eq!(a.fee_rate.sat_per_vb(), 10.0);
+        assert_eq!(a.weight, 400);
+        assert!(a.package.is_none() && a.wtxid.is_none() && a.bip125_replaceable.is_none());
+        assert_eq!(a.package_role(), "unknown");
+        assert_eq!(a.effective_fee_rate().sat_per_vb(), 10.0);
+        assert!(a.time > 0);
+
+        //a later poll keeps the first-seen time of known txns and adds the new ones
+        server.set("/mempool/recent", recent_json(&[("c", 300, 150), ("a", 1_000, 100)]));
+        let txids = source.mempool_txids().expect("mempool txids");
+        assert_eq!(txids.len(), 3);
+        assert_eq!(source.mempool_entry("a").expect("a tracked").time, a.time);
+
+        //mined txns are dropped with the block, replaced ones at the next reconciliation
+        server.set("/mempool/recent", recent_json(&[("c", 300, 150)]));
+        server.set("/block/h/txids", r#"["cb","a"]"#);
+        server.set("/block/h/txs/0", r#"[{"txid":"cb","vin":[{"is_coinbase":true}]},{"txid":"a","vin":[{"is_coinbase":false}]}]"#);
+        let block_txns = source.block_txns("h").expect("block txns");
+        assert!(block_txns[0].is_coinbase() && !block_txns[1].is_coinbase());
+        assert!(!source.mempool_txids().expect("mempool txids").contains("a"));
+
+        source.reconcile_interval = Duration::ZERO;
+        server.set("/mempool/txids", r#"["c"]"#);
+        assert_eq!(source.mempool_txids().expect("mempool txids"), HashSet::from(["c".to_string()]));
+
+        assert!(server.requests().iter().all(|path| !path.starts_with("/tx/")));
+    }
+
+    #[test]
+    fn failed_requests_are_errors() {
+        let server = TestServer::start();
+        let source = EsploraSource::new(&server.base_url);
+
+        assert!(source.mempool_txids().is_err());
+        assert!(source.mempool_txns().is_err());
+    }
+}
@@ -0,0 +1,137 @@
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use crate::esplora::EsploraClient;
+use crate::fee_rate::FeeRate;
+use crate::mempool_data::MempoolTransaction;
+
+//mempool.space's /api/v1/fees/recommended, in sat/vB
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct RecommendedFees {
+    pub fastest_fee: f64,
+    pub half_hour_fee: f64,
+    pub hour_fee: f64,
+    pub economy_fee: f64,
+    pub minimum_fee: f64,
+}
+
+//one of mempool.space's projected blocks from /api/v1/fees/mempool-blocks, the first being its next block template
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ProjectedBlock {
+    pub block_size: u64,
+    #[serde(rename = "blockVSize")]
+    pub block_vsize: f64,
+    pub n_tx: usize,
+    pub total_fees: u64,
+    pub median_fee: f64,
+    //min to max fee rate of the block's transactions, in sat/vB
+    pub fee_range: Vec<f64>,
+}
+
+impl ProjectedBlock {
+    pub fn min_fee(&self) -> Option<f64> {
+        self.fee_range.first().copied()
+    }
+
+    pub fn max_fee(&self) -> Option<f64> {
+        self.fee_range.last().copied()
+    }
+}
+
+//our BlockBuilder template for the snapshot, reduced to what mempool.space reports for its projected next block
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct TemplateSummary {
+    pub txns_count: usize,
+    pub vsize: u64,
+    pub total_fees: u64,
+    pub median_fee_rate: FeeRate,
+    pub min_fee_rate: FeeRate,
+    pub max_fee_rate: FeeRate,
+}
+
+impl TemplateSummary {
+    //fee rates are effective ones, as mempool.space rates a transaction by its package
+    pub fn from_template(template_txns: &[MempoolTransaction]) -> Self {
+        let mut fee_rates: Vec<FeeRate> = template_txns.iter().map(|txn| txn.effective_fee_rate()).collect();
+        fee_rates.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+
+        let nan = FeeRate::from_sat_per_vb(f64::NAN);
+
+        TemplateSummary {
+            txns_count: template_txns.len(),
+            vsize: template_txns.iter().map(|txn| txn.vsize as u64).sum(),
+            total_fees: template_txns.iter().map(|txn| txn.fee).sum(),
+            median_fee_rate: fee_rates.get(fee_rates.len() / 2).copied().unwrap_or(nan),
+            min_fee_rate: fee_rates.first().copied().unwrap_or(nan),
+            max_fee_rate: fee_rates.last().copied().unwrap_or(nan),
+        }
+    }
+}
+
+//mempool.space's view of the mempool next to our template for the same snapshot
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FeeComparison {
+    pub recommended: RecommendedFees,
+    pub projected_blocks: Vec<ProjectedBlock>,
+    pub template: TemplateSummary,
+}
+
+impl FeeComparison {
+    //the client is rooted at the api, e.g. https://mempool.space/api
+    pub fn fetch(client: &EsploraClient, template_txns: &[MempoolTransaction]) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let recommended: RecommendedFees = client.get_json("/v1/fees/recommended")?;
+        let projected_blocks: Vec<ProjectedBlock> = client.get_json("/v1/fees/mempool-blocks")?;
+
+        Ok(FeeComparison {
+            recommended,
+            projected_blocks,
+            template: TemplateSummary::from_template(template_txns),
+        })
+    }
+
+    pub fn next_projected_block(&self) -> Option<&ProjectedBlock> {
+        self.projected_blocks.first()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::TestServer;
+
+    #[test]
+    fn fetches_mempool_space_fees_next_to_our_template() {
+        let server = TestServer::start();
+        server.set("/v1/fees/recommended", r#"{"fastestFee":12,"halfHourFee":9,"hourFee":7,"economyFee":3,"minimumFee":1}"#);
+        server.set("/v1/fees/mempool-blocks", r#"[{"blockSize":9000,"blockVSize":4000.5,"nTx":3,"totalFees":50000,"medianFee":10.5,"feeRange":[1.0,5.0,30.0]}]"#);
+
+        let template_txns = [
+            MempoolTransaction::for_test("a", 3_000, 100, &[]),
+            MempoolTransaction::for_test("b", 1_000, 100, &[]),
+            MempoolTransaction::for_test("c", 2_000, 100, &[]),
+        ];
+        let comparison = FeeComparison::fetch(&EsploraClient::new(&server.base_url), &template_txns).expect("fee comparison");
+
+        assert_eq!(comparison.recommended.fastest_fee, 12.0);
+        assert_eq!(comparison.recommended.minimum_fee, 1.0);
+
+        let projected_block = comparison.next_projected_block().expect("projected block");
+        assert_eq!(projected_block.n_tx, 3);
+        assert_eq!(projected_block.min_fee(), Some(1.0));
+        assert_eq!(projected_block.max_fee(), Some(30.0));
+
+        assert_eq!(comparison.template.txns_count, 3);
+        assert_eq!(comparison.template.total_fees, 6_000);
+        assert_eq!(comparison.template.median_fee_rate.sat_per_vb(), 20.0);
+        assert_eq!(comparison.template.min_fee_rate.sat_per_vb(), 10.0);
+    }
+
+    #[test]
+    fn a_missing_endpoint_is_an_error() {
+        let server = TestServer::start();
+        server.set("/v1/fees/recommended", r#"{"fastestFee":12,"halfHourFee":9,"hourFee":7,"economyFee":3,"minimumFee":1}"#);
+
+        assert!(FeeComparison::fetch(&EsploraClient::new(&server.base_url), &[]).is_err());
+    }
+}
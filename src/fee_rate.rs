@@ -0,0 +1,103 @@
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+const WITNESS_SCALE_FACTOR: f64 = 4.0;
+const SATS_PER_BTC: f64 = 100_000_000.0;
+
+//a fee rate, held internally in sat/vB so values from different sources can be compared directly
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, PartialOrd)]
+#[serde(transparent)]
+pub struct FeeRate(f64);
+
+impl FeeRate {
+    pub fn from_sat_per_vb(sat_per_vb: f64) -> Self {
+        FeeRate(sat_per_vb)
+    }
+
+    pub fn from_sat_per_wu(sat_per_wu: f64) -> Self {
+        FeeRate(sat_per_wu * WITNESS_SCALE_FACTOR)
+    }
+
+    pub fn from_btc_per_kvb(btc_per_kvb: f64) -> Self {
+        FeeRate(btc_per_kvb * SATS_PER_BTC / 1000.0)
+    }
+
+    pub fn from_fee_and_vsize(fee: u64, vsize: u32) -> Self {
+        FeeRate(fee as f64 / vsize as f64)
+    }
+
+    pub fn sat_per_vb(&self) -> f64 {
+        self.0
+    }
+
+    pub fn sat_per_wu(&self) -> f64 {
+        self.0 / WITNESS_SCALE_FACTOR
+    }
+
+    pub fn btc_per_kvb(&self) -> f64 {
+        self.0 * 1000.0 / SATS_PER_BTC
+    }
+
+    pub fn in_units(&self, units: FeeRateUnit) -> f64 {
+        match units {
+            FeeRateUnit::SatPerVb => self.sat_per_vb(),
+            FeeRateUnit::SatPerWu => self.sat_per_wu(),
+            FeeRateUnit::BtcPerKvb => self.btc_per_kvb(),
+        }
+    }
+
+    pub fn is_nan(&self) -> bool {
+        self.0.is_nan()
+    }
+}
+
+impl fmt::Display for FeeRate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} sat/vB", self.0)
+    }
+}
+
+#[derive(ValueEnum, Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+pub enum FeeRateUnit {
+    #[default]
+    SatPerVb,
+    SatPerWu,
+    BtcPerKvb,
+}
+
+impl FeeRateUnit {
+    //appended to csv column names, e.g. mempool_fee_rate_estimate_sat_per_vb
+    pub fn column_suffix(&self) -> &'static str {
+        match self {
+            FeeRateUnit::SatPerVb => "sat_per_vb",
+            FeeRateUnit::SatPerWu => "sat_per_wu",
+            FeeRateUnit::BtcPerKvb => "btc_per_kvb",
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            FeeRateUnit::SatPerVb => "sat/vB",
+            FeeRateUnit::SatPerWu => "sat/WU",
+            FeeRateUnit::BtcPerKvb => "BTC/kvB",
+        }
+    }
+
+    //decimal places worth showing when printing a rate in this unit
+    pub fn decimals(&self) -> usize {
+        match self {
+            FeeRateUnit::SatPerVb | FeeRateUnit::SatPerWu => 4,
+            FeeRateUnit::BtcPerKvb => 8,
+        }
+    }
+
+    pub fn from_column_suffix(suffix: &str) -> Option<Self> {
+        match suffix {
+            "sat_per_vb" => Some(FeeRateUnit::SatPerVb),
+            "sat_per_wu" => Some(FeeRateUnit::SatPerWu),
+            "btc_per_kvb" => Some(FeeRateUnit::BtcPerKvb),
+            _ => None,
+        }
+    }
+}
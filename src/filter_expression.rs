@@ -0,0 +1,313 @@
+use std::fmt;
+use std::sync::Arc;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use crate::config::Comparison;
+use crate::mempool_data::MempoolTransaction;
+use crate::mempool_data_subsets::{AndFilter, FieldFilter, FieldValue, MempoolTransactionFilter, NotFilter, OrFilter, TxnField};
+
+// Grammar, lowest precedence first:
+//   expr       := and_expr ("or" and_expr)*
+//   and_expr   := not_expr ("and" not_expr)*
+//   not_expr   := "not" not_expr | primary
+//   primary    := "(" expr ")" | field <op> value | bool_field
+//   op         := > | >= | < | <= | == | = | !=
+//   value      := number | true | false | "quoted text"
+//   number     := ["-"] digits ["." digits]
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Number(f64),
+    Text(String),
+    Op(Comparison),
+    LeftParen,
+    RightParen,
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = vec![];
+    let chars: Vec<char> = input.chars().collect();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+
+        if c.is_whitespace() {
+            i += 1;
+        } else if c == '(' {
+            tokens.push(Token::LeftParen);
+            i += 1;
+        } else if c == ')' {
+            tokens.push(Token::RightParen);
+            i += 1;
+        } else if c == '>' || c == '<' || c == '=' || c == '!' {
+            let next_is_equals = chars.get(i + 1) == Some(&'=');
+            let op = match (c, next_is_equals) {
+                ('>', true) => Comparison::GreaterThanOrEquals,
+                ('>', false) => Comparison::GreaterThan,
+                ('<', true) => Comparison::LessThanOrEquals,
+                ('<', false) => Comparison::LessThan,
+                ('=', _) => Comparison::Equals,
+                ('!', true) => Comparison::NotEquals,
+                _ => return Err(format!("Unexpected '!' at position {}. Did you mean '!='?", i)),
+            };
+            tokens.push(Token::Op(op));
+            i += if next_is_equals { 2 } else { 1 };
+        } else if c == '"' || c == '\'' {
+            let end = chars[i + 1..].iter().position(|&q| q == c)
+                .ok_or_else(|| format!("Unterminated string starting at position {}", i))?;
+            tokens.push(Token::Text(chars[i + 1..i + 1 + end].iter().collect()));
+            i += end + 2;
+        } else if c.is_ascii_digit() || c == '.' || (c == '-' && chars.get(i + 1).is_some_and(|next| next.is_ascii_digit() || *next == '.')) {
+            let start = i;
+            if c == '-' {
+                i += 1;
+            }
+            while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                i += 1;
+            }
+            let number: String = chars[start..i].iter().collect();
+            let value = number.parse::<f64>().map_err(|_| format!("Invalid number '{}'", number))?;
+            tokens.push(Token::Number(value));
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_' || chars[i] == '-') {
+                i += 1;
+            }
+            tokens.push(Token::Ident(chars[start..i].iter().collect::<String>().to_lowercase()));
+        } else {
+            return Err(format!("Unexpected character '{}' at position {}", c, i));
+        }
+    }
+
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn next_is_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Some(Token::Ident(ident)) if ident == keyword)
+    }
+
+    fn parse_or(&mut self) -> Result<Box<dyn MempoolTransactionFilter>, String> {
+        let mut filters = vec![self.parse_and()?];
+
+        while self.next_is_keyword("or") {
+            self.next();
+            filters.push(self.parse_and()?);
+        }
+
+        if filters.len() == 1 {
+            Ok(filters.remove(0))
+        } else {
+            Ok(Box::new(OrFilter { filters }))
+        }
+    }
+
+    fn parse_and(&mut self) -> Result<Box<dyn MempoolTransactionFilter>, String> {
+        let mut filters = vec![self.parse_not()?];
+
+        while self.next_is_keyword("and") {
+            self.next();
+            filters.push(self.parse_not()?);
+        }
+
+        if filters.len() == 1 {
+            Ok(filters.remove(0))
+        } else {
+            Ok(Box::new(AndFilter { filters }))
+        }
+    }
+
+    fn parse_not(&mut self) -> Result<Box<dyn MempoolTransactionFilter>, String> {
+        if self.next_is_keyword("not") {
+            self.next();
+            let filter = self.parse_not()?;
+            return Ok(Box::new(NotFilter { filter }));
+        }
+
+        self.parse_primary()
+    }
+
+    fn parse_primary(&mut self) -> Result<Box<dyn MempoolTransactionFilter>, String> {
+        match self.next() {
+            Some(Token::LeftParen) => {
+                let filter = self.parse_or()?;
+                match self.next() {
+                    Some(Token::RightParen) => Ok(filter),
+                    _ => Err("Missing closing ')'".to_string()),
+                }
+            }
+            Some(Token::Ident(name)) => {
+                let field = TxnField::from_name(&name).ok_or_else(|| format!("Unknown field '{}'", name))?;
+                self.parse_field_filter(field, &name)
+            }
+            Some(token) => Err(format!("Expected a field or '(' but found {:?}", token)),
+            None => Err("Unexpected end of filter expression".to_string()),
+        }
+    }
+
+    fn parse_field_filter(&mut self, field: TxnField, name: &str) -> Result<Box<dyn MempoolTransactionFilter>, String> {
+        let comparison = match self.peek() {
+            Some(Token::Op(comparison)) => comparison.clone(),
+            //a bare boolean field such as `rbf` means `rbf == true`
+            _ if field.is_bool() => {
+                return Ok(Box::new(FieldFilter { field, comparison: Comparison::Equals, value: FieldValue::Bool(true) }));
+            }
+            _ => return Err(format!("Expected a comparison after '{}'", name)),
+        };
+        self.next();
+
+        let value = match (self.next(), field.is_bool(), field.is_text()) {
+            (Some(Token::Ident(ident)), true, _) if ident == "true" => FieldValue::Bool(true),
+            (Some(Token::Ident(ident)), true, _) if ident == "false" => FieldValue::Bool(false),
+            (Some(Token::Text(text)), _, true) => FieldValue::Text(text),
+            (Some(Token::Ident(ident)), _, true) => FieldValue::Text(ident),
+            (Some(Token::Number(number)), false, false) => FieldValue::Number(number),
+            (Some(token), _, _) => return Err(format!("Invalid value {:?} for field '{}'", token, name)),
+            (None, _, _) => return Err(format!("Missing value for field '{}'", name)),
+        };
+
+        let equality_only = matches!(comparison, Comparison::Equals | Comparison::NotEquals);
+        if (field.is_bool() || field.is_text()) && !equality_only {
+            return Err(format!("Field '{}' only supports == and !=", name));
+        }
+
+        Ok(Box::new(FieldFilter { field, comparison, value }))
+    }
+}
+
+//fields named anywhere in the expression, whether or not it parses
+pub fn referenced_fields(input: &str) -> Vec<TxnField> {
+    tokenize(input).unwrap_or_default()
+        .into_iter()
+        .filter_map(|token| match token {
+            Token::Ident(name) => TxnField::from_name(&name),
+            _ => None,
+        })
+        .collect()
+}
+
+pub fn parse_filter_expression(input: &str) -> Result<Box<dyn MempoolTransactionFilter>, String> {
+    let tokens = tokenize(input)?;
+    if tokens.is_empty() {
+        return Err("Empty filter expression".to_string());
+    }
+
+    let mut parser = Parser { tokens, position: 0 };
+    let filter = parser.parse_or()?;
+
+    if let Some(token) = parser.peek() {
+        return Err(format!("Unexpected {:?} after the end of the expression", token));
+    }
+
+    Ok(filter)
+}
+
+//an expression parsed once when the config is read. it is saved in the run manifest as the text it was parsed from
+#[derive(Clone)]
+pub struct FilterExpression {
+    source: String,
+    filter: Arc<dyn MempoolTransactionFilter>,
+}
+
+impl FilterExpression {
+    pub fn parse(input: &str) -> Result<Self, String> {
+        Ok(FilterExpression { source: input.to_string(), filter: Arc::from(parse_filter_expression(input)?) })
+    }
+
+    pub fn referenced_fields(&self) -> Vec<TxnField> {
+        referenced_fields(&self.source)
+    }
+}
+
+impl MempoolTransactionFilter for FilterExpression {
+    fn filter(&self, txn: &MempoolTransaction) -> bool {
+        self.filter.filter(txn)
+    }
+
+    fn evaluate(&self, txn: &MempoolTransaction) -> Option<bool> {
+        self.filter.evaluate(txn)
+    }
+}
+
+impl fmt::Debug for FilterExpression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.source)
+    }
+}
+
+impl Serialize for FilterExpression {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.source)
+    }
+}
+
+impl<'de> Deserialize<'de> for FilterExpression {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let source = String::deserialize(deserializer)?;
+        FilterExpression::parse(&source).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokenizes_negative_numbers() {
+        assert_eq!(tokenize("x > -1.5").unwrap()[2], Token::Number(-1.5));
+        assert_eq!(tokenize("x>-.5").unwrap()[2], Token::Number(-0.5));
+        assert!(tokenize("x > -").is_err());
+    }
+
+    #[test]
+    fn filters_with_negative_literals() {
+        let txn = MempoolTransaction::for_test(&"a".repeat(64), 1000, 100, &[]);
+
+        assert!(FilterExpression::parse("fee_rate > -1").unwrap().filter(&txn));
+        assert!(!FilterExpression::parse("fee_rate < -1 or not fee_rate > -1").unwrap().filter(&txn));
+    }
+
+    #[test]
+    fn unknown_fields_match_neither_a_filter_nor_its_negation() {
+        //as esplora reports it, without package stats or rbf signaling
+        let mut txn = MempoolTransaction::for_test(&"a".repeat(64), 1000, 100, &[]);
+        txn.package = None;
+        txn.bip125_replaceable = None;
+
+        for expression in ["rbf", "not rbf", "ancestors > 0", "not ancestors > 0", "not (rbf and fee_rate > 1)"] {
+            assert!(!FilterExpression::parse(expression).unwrap().filter(&txn), "{} matched", expression);
+        }
+
+        //a known operand can still settle the expression
+        assert!(FilterExpression::parse("not (rbf and fee_rate > 100)").unwrap().filter(&txn));
+        assert!(FilterExpression::parse("not rbf or fee_rate > 1").unwrap().filter(&txn));
+        assert!(!FilterExpression::parse("not (rbf or fee_rate > 1)").unwrap().filter(&txn));
+    }
+
+    #[test]
+    fn round_trips_through_the_manifest_as_text() {
+        let expression = FilterExpression::parse("fee_rate >= 10 and rbf").unwrap();
+        let json = serde_json::to_string(&expression).unwrap();
+        assert_eq!(json, "\"fee_rate >= 10 and rbf\"");
+
+        let parsed: FilterExpression = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.referenced_fields(), expression.referenced_fields());
+        assert!(serde_json::from_str::<FilterExpression>("\"fee_rate >\"").is_err());
+    }
+}
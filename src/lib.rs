@@ -3,5 +3,19 @@ pub mod runner;
 pub mod config;
 mod mempool_data;
 mod mempool_data_subsets;
+mod filter_expression;
 mod block_data;
-mod result;
\ No newline at end of file
+mod result;
+mod fee_rate;
+mod dashboard;
+mod manifest;
+mod txn_shape;
+mod mempool_state;
+mod zmq_events;
+mod chain_source;
+mod esplora;
+mod fee_comparison;
+mod template_diff;
+mod block_fidelity;
+#[cfg(test)]
+mod test_server;
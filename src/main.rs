@@ -1,11 +1,13 @@
 use std::error::Error;
 
-use mempool_fee_analyzer::{config::parse_config, runner::run_tasks};
+use mempool_fee_analyzer::{config::{parse_invocation, Invocation}, runner::{resume_tasks, run_tasks}};
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
-    let config = parse_config();
-    let _ = run_tasks(config).await?;
+    match parse_invocation() {
+        Invocation::Run(config) => run_tasks(*config).await?,
+        Invocation::Resume(resume_config) => resume_tasks(resume_config).await?,
+    }
 
     Ok(())
 }
@@ -0,0 +1,61 @@
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use chrono::Utc;
+use crate::config::Config;
+
+pub const MANIFEST_FILE_NAME: &str = "manifest.json";
+
+//everything needed to tell what a run directory's results were produced with
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RunManifest {
+    pub run_id: String,
+    pub tool_version: String,
+    pub strategy: String,
+    pub config: Config,
+    pub start_height: u32,
+    pub started_at: String
+}
+
+impl RunManifest {
+    pub fn new(config: &Config, run_id: &str, strategy: &str, start_height: u32, started_at: &str) -> Self {
+        RunManifest {
+            run_id: run_id.to_string(),
+            tool_version: env!("CARGO_PKG_VERSION").to_string(),
+            strategy: strategy.to_string(),
+            config: config.clone(),
+            start_height,
+            started_at: started_at.to_string()
+        }
+    }
+
+    pub fn save(&self, run_dir: &Path) -> Result<(), Box<dyn Error>> {
+        let file = File::create(run_dir.join(MANIFEST_FILE_NAME))?;
+        let writer = BufWriter::new(file);
+        serde_json::to_writer_pretty(writer, self)?;
+        Ok(())
+    }
+
+    pub fn load(run_dir: &Path) -> Result<Self, Box<dyn Error>> {
+        let data = fs::read_to_string(run_dir.join(MANIFEST_FILE_NAME))?;
+        let manifest = serde_json::from_str(&data)?;
+        Ok(manifest)
+    }
+}
+
+//run ids sort by start time; the pid keeps concurrent runs started in the same second apart
+pub fn generate_run_id(strategy_name: &str) -> String {
+    format!("{}-{}-{}", Utc::now().format("%Y%m%dT%H%M%SZ"), strategy_name, std::process::id())
+}
+
+//fails if the run directory already exists so results of different runs never mix
+pub fn create_run_dir(output_dir: &Path, run_id: &str) -> Result<PathBuf, Box<dyn Error>> {
+    fs::create_dir_all(output_dir)?;
+
+    let run_dir = output_dir.join(run_id);
+    fs::create_dir(&run_dir)?;
+
+    Ok(run_dir)
+}
@@ -2,11 +2,13 @@
 use std::{error::Error, os::unix::process::parent_id, path::PathBuf, process::Command};
 use hex::FromHex;
 use serde::{Deserialize, Serialize};
-use serde_json;
 use std::fs;
 use std::path::Path;
 use std::io;
 use std::collections::HashMap;
+use crate::fee_rate::FeeRate;
+use crate::runner::bcli;
+use crate::txn_shape::TxnShape;
 
 #[derive(Serialize, Deserialize, Debug)]
 struct Fees {
@@ -53,10 +55,40 @@ pub struct MempoolTransaction {
     pub txid: String,
     pub fee: u64,
     pub weight: u64,
-    pub fee_rate: f64,
+    pub fee_rate: FeeRate,
     pub parent_txids: Vec<String>,
     pub inputs_count: u32,
-    pub outputs_count: u32
+    pub outputs_count: u32,
+    //None where the source does not report it, as with esplora
+    pub wtxid: Option<String>,
+    pub vsize: u32,
+    pub modified_fee: u64,
+    pub package: Option<PackageStats>,
+    pub bip125_replaceable: Option<bool>,
+    pub unbroadcast: bool,
+    pub time: u64,
+    pub height: u32,
+    //only set when the run decodes transactions
+    #[serde(default)]
+    pub shape: Option<TxnShape>
+}
+
+//the ancestor and descendant stats getrawmempool keeps for each transaction. sources without them
+//leave the package unknown rather than passing each transaction off as standalone
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PackageStats {
+    pub ancestor_count: u32,
+    pub ancestor_size: u32,
+    pub ancestor_fee: u64,
+    pub descendant_count: u32,
+    pub descendant_size: u32,
+    pub descendant_fee: u64,
+    pub child_txids: Vec<String>,
+}
+
+//getrawmempool reports fees in BTC
+fn btc_to_sats(btc: f64) -> u64 {
+    (btc * 100_000_000.0).round() as u64
 }
 
 impl MempoolTransaction {
@@ -65,26 +97,166 @@ impl MempoolTransaction {
         let mut mempool_txns: Vec<MempoolTransaction> = vec![];
 
         for (txid, data) in mempool_data {
-            let fee: u64 = (data.fees.base * 100_000_000.0) as u64;
-            let weight = data.weight;
-            let fee_rate: f64 = fee as f64 / weight as f64;
-            let parent_txids = &data.depends;
-            let inputs_count = 0;
-            let outputs_count = 0;
-
-            mempool_txns.push(MempoolTransaction {
-                txid: txid.to_string(),
-                fee,
-                weight,
-                fee_rate,
-                parent_txids: parent_txids.to_vec(),
-                inputs_count,
-                outputs_count
-            })
+            mempool_txns.push(MempoolTransaction::from_mempool_data(txid, data));
         }
 
         Ok(mempool_txns)
     }
 
+    //a single transaction via getmempoolentry. None if it is not (or no longer) in the mempool
+    pub fn fetch_mempool_entry(txid: &str) -> Option<MempoolTransaction> {
+        let raw_entry = bcli(&format!("getmempoolentry {}", txid)).ok()?;
+        let data: MempoolData = serde_json::from_slice(&raw_entry).ok()?;
+
+        Some(MempoolTransaction::from_mempool_data(txid, &data))
+    }
+
+    fn from_mempool_data(txid: &str, data: &MempoolData) -> MempoolTransaction {
+        let fee: u64 = btc_to_sats(data.fees.base);
+        let weight = data.weight;
+        let fee_rate = FeeRate::from_fee_and_vsize(fee, data.vsize);
+        let parent_txids = &data.depends;
+        let inputs_count = 0;
+        let outputs_count = 0;
+
+        MempoolTransaction {
+            txid: txid.to_string(),
+            fee,
+            weight,
+            fee_rate,
+            parent_txids: parent_txids.to_vec(),
+            inputs_count,
+            outputs_count,
+            wtxid: Some(data.wtxid.clone()),
+            vsize: data.vsize,
+            modified_fee: btc_to_sats(data.fees.modified),
+            package: Some(PackageStats {
+                ancestor_count: data.ancestorcount,
+                ancestor_size: data.ancestorsize,
+                ancestor_fee: btc_to_sats(data.fees.ancestor),
+                descendant_count: data.descendantcount,
+                descendant_size: data.descendantsize,
+                descendant_fee: btc_to_sats(data.fees.descendant),
+                child_txids: data.spentby.clone(),
+            }),
+            bip125_replaceable: Some(data.bip125_replaceable),
+            unbroadcast: data.unbroadcast,
+            time: data.time,
+            height: data.height,
+            shape: None
+        }
+    }
+
+    pub fn set_shape(&mut self, shape: TxnShape) {
+        self.inputs_count = shape.input_script_types.len() as u32;
+        self.outputs_count = shape.output_script_types.len() as u32;
+        self.shape = Some(shape);
+    }
+
+    //seconds the transaction had spent in the mempool at the given unix time
+    pub fn age(&self, at: u64) -> u64 {
+        at.saturating_sub(self.time)
+    }
+
+    //fee rate including any prioritisetransaction adjustment, which is what miners sort by
+    pub fn modified_fee_rate(&self) -> FeeRate {
+        FeeRate::from_fee_and_vsize(self.modified_fee, self.vsize)
+    }
 
+    //fee rate of the transaction together with all of its unconfirmed ancestors
+    pub fn ancestor_fee_rate(&self) -> Option<FeeRate> {
+        self.package.as_ref().map(|package| FeeRate::from_fee_and_vsize(package.ancestor_fee, package.ancestor_size))
+    }
+
+    //fee rate of the transaction together with all of its unconfirmed descendants
+    pub fn descendant_fee_rate(&self) -> Option<FeeRate> {
+        self.package.as_ref().map(|package| FeeRate::from_fee_and_vsize(package.descendant_fee, package.descendant_size))
+    }
+
+    //children in the mempool, none if the package is unknown
+    pub fn child_txids(&self) -> &[String] {
+        self.package.as_ref().map_or(&[], |package| &package.child_txids)
+    }
+
+    //false when the package is unknown
+    pub fn has_unconfirmed_ancestors(&self) -> bool {
+        self.package.as_ref().is_some_and(|package| package.ancestor_count > 1)
+    }
+
+    fn has_unconfirmed_descendants(&self) -> bool {
+        self.package.as_ref().is_some_and(|package| package.descendant_count > 1)
+    }
+
+    //pays a higher fee rate than its ancestors so that they get mined along with it
+    pub fn is_cpfp_child(&self) -> bool {
+        self.has_unconfirmed_ancestors() && self.ancestor_fee_rate().is_some_and(|ancestor_fee_rate| self.fee_rate > ancestor_fee_rate)
+    }
+
+    //the fee rate the transaction is effectively mined at as part of its package: a child can
+    //be no better than its ancestors allow, and a parent is pulled up by children paying more.
+    //just the fee rate when the package is unknown
+    pub fn effective_fee_rate(&self) -> FeeRate {
+        let mut fee_rate = self.fee_rate;
+
+        if let Some(ancestor_fee_rate) = self.ancestor_fee_rate().filter(|_| self.has_unconfirmed_ancestors()) {
+            if ancestor_fee_rate < fee_rate {
+                fee_rate = ancestor_fee_rate;
+            }
+        }
+
+        if let Some(descendant_fee_rate) = self.descendant_fee_rate().filter(|_| self.has_unconfirmed_descendants()) {
+            if descendant_fee_rate > fee_rate {
+                fee_rate = descendant_fee_rate;
+            }
+        }
+
+        fee_rate
+    }
+
+    pub fn package_role(&self) -> &'static str {
+        if self.package.is_none() {
+            "unknown"
+        } else if self.is_cpfp_child() {
+            "cpfp-child"
+        } else if self.has_unconfirmed_descendants() && self.descendant_fee_rate().is_some_and(|descendant_fee_rate| descendant_fee_rate > self.fee_rate) {
+            "bumped-parent"
+        } else if self.has_unconfirmed_ancestors() || self.has_unconfirmed_descendants() {
+            "package-member"
+        } else {
+            "standalone"
+        }
+    }
+}
+
+#[cfg(test)]
+impl MempoolTransaction {
+    //a mempool txn with no package around it beyond the given parents
+    pub(crate) fn for_test(txid: &str, fee: u64, vsize: u32, parent_txids: &[&str]) -> Self {
+        MempoolTransaction {
+            txid: txid.to_string(),
+            fee,
+            weight: vsize as u64 * 4,
+            fee_rate: FeeRate::from_fee_and_vsize(fee, vsize),
+            parent_txids: parent_txids.iter().map(|txid| txid.to_string()).collect(),
+            inputs_count: 0,
+            outputs_count: 0,
+            wtxid: Some(txid.to_string()),
+            vsize,
+            modified_fee: fee,
+            package: Some(PackageStats {
+                ancestor_count: 1,
+                ancestor_size: vsize,
+                ancestor_fee: fee,
+                descendant_count: 1,
+                descendant_size: vsize,
+                descendant_fee: fee,
+                child_txids: vec![],
+            }),
+            bip125_replaceable: Some(false),
+            unbroadcast: false,
+            time: 0,
+            height: 0,
+            shape: None
+        }
+    }
 }
@@ -1,27 +1,77 @@
-use crate::mempool_data::MempoolTransaction;
+use crate::mempool_data::{MempoolTransaction, PackageStats};
 use crate::config::Comparison;
+use crate::fee_rate::FeeRate;
+use crate::txn_shape::ScriptType;
+use crate::block_data::BlockTemplate;
+use crate::strategies::select_strategy;
+use serde::{Deserialize, Serialize};
 
-pub trait MempoolTransactionFilter {
+//where the --high-fee-txns/--low-fee-txns threshold comes from
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub enum ThresholdSource {
+    //the estimate of the strategy being evaluated
+    #[default]
+    Strategy,
+    Fixed(FeeRate),
+    //percentile (0-100) of the snapshot mempool's effective fee rates, by transaction count
+    MempoolPercentile(f64),
+    //the lowest fee rate mined in the block before the target block, worked out once per block
+    PrevBlockMinFeeRate,
+    //the estimate of another strategy, by name
+    OtherStrategy(String),
+}
+
+impl ThresholdSource {
+    //block_template is built from mempool_txns
+    pub fn resolve(&self, strategy_estimate: FeeRate, mempool_txns: &[MempoolTransaction], block_template: &BlockTemplate, prev_block_min_fee_rate: Option<FeeRate>) -> FeeRate {
+        match self {
+            ThresholdSource::Strategy => strategy_estimate,
+            ThresholdSource::Fixed(fee_rate) => *fee_rate,
+            ThresholdSource::MempoolPercentile(percentile) => {
+                let mut fee_rates: Vec<FeeRate> = mempool_txns.iter().map(|txn| txn.effective_fee_rate()).collect();
+                if fee_rates.is_empty() {
+                    return FeeRate::from_sat_per_vb(f64::NAN);
+                }
+
+                fee_rates.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+                let rank = (percentile / 100.0 * fee_rates.len() as f64).ceil() as usize;
+                fee_rates[rank.clamp(1, fee_rates.len()) - 1]
+            }
+            //NaN, so the subset is empty, while the previous block's fees are unknown
+            ThresholdSource::PrevBlockMinFeeRate => prev_block_min_fee_rate.unwrap_or(FeeRate::from_sat_per_vb(f64::NAN)),
+            ThresholdSource::OtherStrategy(strategy_name) => select_strategy(strategy_name).estimate_fee_rate(mempool_txns, block_template),
+        }
+    }
+}
+
+//filters parsed from the config are shared by the analysis tasks
+pub trait MempoolTransactionFilter: Send + Sync {
     fn filter(&self, txn: &MempoolTransaction) -> bool;
+
+    //None when the txn lacks a field the filter compares, which stays unknown under not so the txn never matches
+    fn evaluate(&self, txn: &MempoolTransaction) -> Option<bool> {
+        Some(self.filter(txn))
+    }
 }
 
 pub struct LowFeeFilter {
-    pub threshold: f64,
+    pub threshold: FeeRate,
 }
 
+//the fee tiers compare the package fee rate, so a bumped parent or a cpfp child is placed by what it is mined at
 impl MempoolTransactionFilter for LowFeeFilter {
     fn filter(&self, txn: &MempoolTransaction) -> bool {
-        txn.fee_rate < self.threshold
+        txn.effective_fee_rate() < self.threshold
     }
 }
 
 pub struct HighFeeFilter {
-    pub threshold: f64,
+    pub threshold: FeeRate,
 }
 
 impl MempoolTransactionFilter for HighFeeFilter {
     fn filter(&self, txn: &MempoolTransaction) -> bool {
-        txn.fee_rate >= self.threshold
+        txn.effective_fee_rate() >= self.threshold
     }
 }
 
@@ -32,11 +82,7 @@ pub struct InputsCountFilter {
 
 impl MempoolTransactionFilter for InputsCountFilter {
     fn filter(&self, txn: &MempoolTransaction) -> bool {
-        match self.comparison {
-            Comparison::LessThan => txn.inputs_count < self.count,
-            Comparison::GreaterThan => txn.inputs_count > self.count,
-            Comparison::Equals => txn.inputs_count == self.count,
-        }
+        self.comparison.compare(txn.inputs_count, self.count)
     }
 }
 
@@ -47,12 +93,370 @@ pub struct OutputsCountFilter {
 
 impl MempoolTransactionFilter for OutputsCountFilter {
     fn filter(&self, txn: &MempoolTransaction) -> bool {
-        match self.comparison {
-            Comparison::LessThan => txn.outputs_count < self.count,
-            Comparison::GreaterThan => txn.outputs_count > self.count,
-            Comparison::Equals => txn.outputs_count == self.count,
+        self.comparison.compare(txn.outputs_count, self.count)
+    }
+}
+
+pub struct UnconfirmedAncestorsFilter;
+
+impl MempoolTransactionFilter for UnconfirmedAncestorsFilter {
+    fn filter(&self, txn: &MempoolTransaction) -> bool {
+        txn.has_unconfirmed_ancestors()
+    }
+}
+
+pub struct CpfpChildFilter;
+
+impl MempoolTransactionFilter for CpfpChildFilter {
+    fn filter(&self, txn: &MempoolTransaction) -> bool {
+        txn.is_cpfp_child()
+    }
+}
+
+//ancestor counts include the transaction itself, as in getrawmempool
+pub struct AncestorCountFilter {
+    pub min: u32,
+    pub max: u32,
+}
+
+impl MempoolTransactionFilter for AncestorCountFilter {
+    fn filter(&self, txn: &MempoolTransaction) -> bool {
+        txn.package.as_ref().is_some_and(|package| (self.min..=self.max).contains(&package.ancestor_count))
+    }
+}
+
+pub struct RbfSignalingFilter {
+    pub signaling: bool,
+}
+
+impl MempoolTransactionFilter for RbfSignalingFilter {
+    fn filter(&self, txn: &MempoolTransaction) -> bool {
+        txn.bip125_replaceable == Some(self.signaling)
+    }
+}
+
+pub struct AgeFilter {
+    pub comparison: Comparison,
+    pub seconds: u64,
+    //unix time of the snapshot the age is measured at
+    pub snapshot_time: u64,
+}
+
+impl MempoolTransactionFilter for AgeFilter {
+    fn filter(&self, txn: &MempoolTransaction) -> bool {
+        self.comparison.compare(txn.age(self.snapshot_time), self.seconds)
+    }
+}
+
+//lower bound (seconds in the mempool) and label of each age bucket, youngest first
+pub const AGE_BUCKETS: [(u64, &str); 7] = [
+    (0, "<10m"),
+    (600, "10m-1h"),
+    (3_600, "1h-6h"),
+    (21_600, "6h-1d"),
+    (86_400, "1d-3d"),
+    (259_200, "3d-1w"),
+    (604_800, "1w+"),
+];
+
+pub fn age_bucket(age: u64) -> &'static str {
+    AGE_BUCKETS.iter().rev()
+        .find(|(lower_bound, _)| age >= *lower_bound)
+        .map(|(_, label)| *label)
+        .unwrap_or(AGE_BUCKETS[0].1)
+}
+
+//lower bound (vbytes) and label of each vsize bucket, smallest first
+pub const VSIZE_BUCKETS: [(u32, &str); 6] = [
+    (0, "<200vB"),
+    (200, "200-500vB"),
+    (500, "500vB-1kvB"),
+    (1_000, "1-5kvB"),
+    (5_000, "5-20kvB"),
+    (20_000, "20kvB+"),
+];
+
+pub fn vsize_bucket(vsize: u32) -> &'static str {
+    VSIZE_BUCKETS.iter().rev()
+        .find(|(lower_bound, _)| vsize >= *lower_bound)
+        .map(|(_, label)| *label)
+        .unwrap_or(VSIZE_BUCKETS[0].1)
+}
+
+pub struct VsizeBucketFilter {
+    pub bucket: String,
+}
+
+impl MempoolTransactionFilter for VsizeBucketFilter {
+    fn filter(&self, txn: &MempoolTransaction) -> bool {
+        vsize_bucket(txn.vsize) == self.bucket
+    }
+}
+
+//the shape filters below never match a transaction that could not be decoded
+
+//all inputs spend outputs of the given type; mixed selects transactions spending several types
+pub struct InputScriptTypeFilter {
+    pub script_type: ScriptType,
+}
+
+impl MempoolTransactionFilter for InputScriptTypeFilter {
+    fn filter(&self, txn: &MempoolTransaction) -> bool {
+        txn.shape.as_ref().is_some_and(|shape| shape.input_script_type() == self.script_type)
+    }
+}
+
+//any output is of the given type; mixed selects transactions paying to several types
+pub struct OutputScriptTypeFilter {
+    pub script_type: ScriptType,
+}
+
+impl MempoolTransactionFilter for OutputScriptTypeFilter {
+    fn filter(&self, txn: &MempoolTransaction) -> bool {
+        txn.shape.as_ref().is_some_and(|shape| match self.script_type {
+            ScriptType::Mixed => shape.output_script_type() == ScriptType::Mixed,
+            script_type => shape.output_script_types.contains(&script_type),
+        })
+    }
+}
+
+pub struct OpReturnFilter;
+
+impl MempoolTransactionFilter for OpReturnFilter {
+    fn filter(&self, txn: &MempoolTransaction) -> bool {
+        txn.shape.as_ref().is_some_and(|shape| shape.has_op_return)
+    }
+}
+
+pub struct InscriptionFilter;
+
+impl MempoolTransactionFilter for InscriptionFilter {
+    fn filter(&self, txn: &MempoolTransaction) -> bool {
+        txn.shape.as_ref().is_some_and(|shape| shape.has_inscription)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TxnField {
+    Txid,
+    Wtxid,
+    Fee,
+    ModifiedFee,
+    Weight,
+    Vsize,
+    FeeRate,
+    ModifiedFeeRate,
+    AncestorFeeRate,
+    EffectiveFeeRate,
+    InputsCount,
+    OutputsCount,
+    ParentsCount,
+    ChildrenCount,
+    AncestorCount,
+    AncestorSize,
+    AncestorFee,
+    DescendantCount,
+    DescendantSize,
+    DescendantFee,
+    Bip125Replaceable,
+    Unbroadcast,
+    Time,
+    Height,
+    CpfpChild,
+    PackageRole,
+    VsizeBucket,
+    InputScriptType,
+    OutputScriptType,
+    OpReturn,
+    Inscription,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FieldValue {
+    Number(f64),
+    Bool(bool),
+    Text(String),
+    //not reported by the mempool source; a comparison against it is neither true nor false
+    Unknown,
+}
+
+fn package_value(txn: &MempoolTransaction, value: impl Fn(&PackageStats) -> f64) -> FieldValue {
+    txn.package.as_ref().map_or(FieldValue::Unknown, |package| FieldValue::Number(value(package)))
+}
+
+impl TxnField {
+    //names accepted in filter expressions, including the getrawmempool spelling where it differs
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "txid" => Some(TxnField::Txid),
+            "wtxid" => Some(TxnField::Wtxid),
+            "fee" | "base_fee" => Some(TxnField::Fee),
+            "modified_fee" => Some(TxnField::ModifiedFee),
+            "weight" => Some(TxnField::Weight),
+            "vsize" => Some(TxnField::Vsize),
+            "fee_rate" | "feerate" => Some(TxnField::FeeRate),
+            "modified_fee_rate" => Some(TxnField::ModifiedFeeRate),
+            "ancestor_fee_rate" | "package_fee_rate" => Some(TxnField::AncestorFeeRate),
+            "effective_fee_rate" => Some(TxnField::EffectiveFeeRate),
+            "cpfp" | "cpfp_child" => Some(TxnField::CpfpChild),
+            "package_role" => Some(TxnField::PackageRole),
+            "inputs" | "inputs_count" => Some(TxnField::InputsCount),
+            "outputs" | "outputs_count" => Some(TxnField::OutputsCount),
+            "parents" | "depends" => Some(TxnField::ParentsCount),
+            "children" | "spentby" => Some(TxnField::ChildrenCount),
+            "ancestors" | "ancestor_count" | "ancestorcount" => Some(TxnField::AncestorCount),
+            "ancestor_size" | "ancestorsize" => Some(TxnField::AncestorSize),
+            "ancestor_fee" | "ancestor_fees" => Some(TxnField::AncestorFee),
+            "descendants" | "descendant_count" | "descendantcount" => Some(TxnField::DescendantCount),
+            "descendant_size" | "descendantsize" => Some(TxnField::DescendantSize),
+            "descendant_fee" | "descendant_fees" => Some(TxnField::DescendantFee),
+            "rbf" | "bip125_replaceable" | "bip125-replaceable" => Some(TxnField::Bip125Replaceable),
+            "unbroadcast" => Some(TxnField::Unbroadcast),
+            "time" => Some(TxnField::Time),
+            "height" => Some(TxnField::Height),
+            "vsize_bucket" => Some(TxnField::VsizeBucket),
+            "input_type" | "input_script_type" => Some(TxnField::InputScriptType),
+            "output_type" | "output_script_type" => Some(TxnField::OutputScriptType),
+            "op_return" => Some(TxnField::OpReturn),
+            "inscription" => Some(TxnField::Inscription),
+            _ => None,
+        }
+    }
+
+    pub fn value(&self, txn: &MempoolTransaction) -> FieldValue {
+        match self {
+            TxnField::Txid => FieldValue::Text(txn.txid.clone()),
+            TxnField::Wtxid => txn.wtxid.clone().map_or(FieldValue::Unknown, FieldValue::Text),
+            TxnField::Fee => FieldValue::Number(txn.fee as f64),
+            TxnField::ModifiedFee => FieldValue::Number(txn.modified_fee as f64),
+            TxnField::Weight => FieldValue::Number(txn.weight as f64),
+            TxnField::Vsize => FieldValue::Number(txn.vsize as f64),
+            TxnField::FeeRate => FieldValue::Number(txn.fee_rate.sat_per_vb()),
+            TxnField::ModifiedFeeRate => FieldValue::Number(txn.modified_fee_rate().sat_per_vb()),
+            TxnField::AncestorFeeRate => txn.ancestor_fee_rate().map_or(FieldValue::Unknown, |fee_rate| FieldValue::Number(fee_rate.sat_per_vb())),
+            TxnField::EffectiveFeeRate => FieldValue::Number(txn.effective_fee_rate().sat_per_vb()),
+            TxnField::CpfpChild => FieldValue::Bool(txn.is_cpfp_child()),
+            TxnField::PackageRole => FieldValue::Text(txn.package_role().to_string()),
+            TxnField::InputsCount => FieldValue::Number(txn.inputs_count as f64),
+            TxnField::OutputsCount => FieldValue::Number(txn.outputs_count as f64),
+            TxnField::ParentsCount => FieldValue::Number(txn.parent_txids.len() as f64),
+            TxnField::ChildrenCount => package_value(txn, |package| package.child_txids.len() as f64),
+            TxnField::AncestorCount => package_value(txn, |package| package.ancestor_count as f64),
+            TxnField::AncestorSize => package_value(txn, |package| package.ancestor_size as f64),
+            TxnField::AncestorFee => package_value(txn, |package| package.ancestor_fee as f64),
+            TxnField::DescendantCount => package_value(txn, |package| package.descendant_count as f64),
+            TxnField::DescendantSize => package_value(txn, |package| package.descendant_size as f64),
+            TxnField::DescendantFee => package_value(txn, |package| package.descendant_fee as f64),
+            TxnField::Bip125Replaceable => txn.bip125_replaceable.map_or(FieldValue::Unknown, FieldValue::Bool),
+            TxnField::Unbroadcast => FieldValue::Bool(txn.unbroadcast),
+            TxnField::Time => FieldValue::Number(txn.time as f64),
+            TxnField::Height => FieldValue::Number(txn.height as f64),
+            TxnField::VsizeBucket => FieldValue::Text(vsize_bucket(txn.vsize).to_string()),
+            TxnField::InputScriptType => FieldValue::Text(txn.shape.as_ref().map_or("undecoded", |s| s.input_script_type().label()).to_string()),
+            TxnField::OutputScriptType => FieldValue::Text(txn.shape.as_ref().map_or("undecoded", |s| s.output_script_type().label()).to_string()),
+            TxnField::OpReturn => FieldValue::Bool(txn.shape.as_ref().is_some_and(|s| s.has_op_return)),
+            TxnField::Inscription => FieldValue::Bool(txn.shape.as_ref().is_some_and(|s| s.has_inscription)),
         }
     }
+
+    pub fn is_bool(&self) -> bool {
+        matches!(self, TxnField::Bip125Replaceable | TxnField::Unbroadcast | TxnField::CpfpChild | TxnField::OpReturn | TxnField::Inscription)
+    }
+
+    pub fn is_text(&self) -> bool {
+        matches!(self, TxnField::Txid | TxnField::Wtxid | TxnField::PackageRole | TxnField::VsizeBucket | TxnField::InputScriptType | TxnField::OutputScriptType)
+    }
+
+    //fields that are only known once the raw transaction has been decoded
+    pub fn needs_shape(&self) -> bool {
+        matches!(
+            self,
+            TxnField::InputsCount | TxnField::OutputsCount | TxnField::InputScriptType
+                | TxnField::OutputScriptType | TxnField::OpReturn | TxnField::Inscription
+        )
+    }
+}
+
+pub struct FieldFilter {
+    pub field: TxnField,
+    pub comparison: Comparison,
+    pub value: FieldValue,
+}
+
+impl MempoolTransactionFilter for FieldFilter {
+    fn filter(&self, txn: &MempoolTransaction) -> bool {
+        self.evaluate(txn) == Some(true)
+    }
+
+    fn evaluate(&self, txn: &MempoolTransaction) -> Option<bool> {
+        match (self.field.value(txn), &self.value) {
+            (FieldValue::Number(left), FieldValue::Number(right)) => Some(self.comparison.compare(left, *right)),
+            (FieldValue::Bool(left), FieldValue::Bool(right)) => Some(self.comparison.compare(left, *right)),
+            (FieldValue::Text(left), FieldValue::Text(right)) => Some(self.comparison.compare(left.as_str(), right.as_str())),
+            (FieldValue::Unknown, _) => None,
+            _ => Some(false),
+        }
+    }
+}
+
+pub struct AndFilter {
+    pub filters: Vec<Box<dyn MempoolTransactionFilter>>,
+}
+
+//unknown unless a known false operand settles it
+impl MempoolTransactionFilter for AndFilter {
+    fn filter(&self, txn: &MempoolTransaction) -> bool {
+        self.evaluate(txn) == Some(true)
+    }
+
+    fn evaluate(&self, txn: &MempoolTransaction) -> Option<bool> {
+        let results: Vec<Option<bool>> = self.filters.iter().map(|f| f.evaluate(txn)).collect();
+
+        if results.contains(&Some(false)) {
+            Some(false)
+        } else if results.contains(&None) {
+            None
+        } else {
+            Some(true)
+        }
+    }
+}
+
+pub struct OrFilter {
+    pub filters: Vec<Box<dyn MempoolTransactionFilter>>,
+}
+
+//unknown unless a known true operand settles it
+impl MempoolTransactionFilter for OrFilter {
+    fn filter(&self, txn: &MempoolTransaction) -> bool {
+        self.evaluate(txn) == Some(true)
+    }
+
+    fn evaluate(&self, txn: &MempoolTransaction) -> Option<bool> {
+        let results: Vec<Option<bool>> = self.filters.iter().map(|f| f.evaluate(txn)).collect();
+
+        if results.contains(&Some(true)) {
+            Some(true)
+        } else if results.contains(&None) {
+            None
+        } else {
+            Some(false)
+        }
+    }
+}
+
+pub struct NotFilter {
+    pub filter: Box<dyn MempoolTransactionFilter>,
+}
+
+impl MempoolTransactionFilter for NotFilter {
+    fn filter(&self, txn: &MempoolTransaction) -> bool {
+        self.evaluate(txn) == Some(true)
+    }
+
+    fn evaluate(&self, txn: &MempoolTransaction) -> Option<bool> {
+        self.filter.evaluate(txn).map(|result| !result)
+    }
 }
 
 pub fn filter_mempool_txns(txns: &[MempoolTransaction], filters: &[Box<dyn MempoolTransactionFilter>]) -> Vec<MempoolTransaction> {
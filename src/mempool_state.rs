@@ -0,0 +1,168 @@
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use crate::mempool_data::{MempoolData, MempoolTransaction};
+use crate::runner::bcli;
+use crate::txn_shape::TxnShapeCache;
+
+//past this many unknown txids, e.g. after the node restarts, one verbose getrawmempool is
+//cheaper than a getmempoolentry per transaction
+const FULL_REFETCH_THRESHOLD: usize = 5000;
+
+//where the mempool is read from. bitcoind in a run, or an esplora instance when there is no node
+pub trait MempoolSource: Send + Sync {
+    //every transaction in the mempool, to start from
+    fn mempool_txns(&self) -> Result<Vec<MempoolTransaction>, Box<dyn Error + Send + Sync>>;
+    fn mempool_txids(&self) -> Result<HashSet<String>, Box<dyn Error + Send + Sync>>;
+    //None if the transaction is not (or no longer) in the mempool
+    fn mempool_entry(&self, txid: &str) -> Option<MempoolTransaction>;
+}
+
+pub struct BitcoindMempoolSource;
+
+impl MempoolSource for BitcoindMempoolSource {
+    fn mempool_txns(&self) -> Result<Vec<MempoolTransaction>, Box<dyn Error + Send + Sync>> {
+        let raw_mempool_data: Vec<u8> = bcli("getrawmempool true").map_err(|e| e.to_string())?;
+        let mempool_data: HashMap<String, MempoolData> = serde_json::from_slice(&raw_mempool_data)?;
+
+        MempoolTransaction::fetch_mempool_txns(&mempool_data).map_err(|e| e.to_string().into())
+    }
+
+    fn mempool_txids(&self) -> Result<HashSet<String>, Box<dyn Error + Send + Sync>> {
+        let raw_mempool_txids: Vec<u8> = bcli("getrawmempool false").map_err(|e| e.to_string())?;
+        Ok(serde_json::from_slice(&raw_mempool_txids)?)
+    }
+
+    fn mempool_entry(&self, txid: &str) -> Option<MempoolTransaction> {
+        MempoolTransaction::fetch_mempool_entry(txid)
+    }
+}
+
+//txids that entered and left the mempool since the delta was last taken
+#[derive(Debug, Default, Clone)]
+pub struct MempoolDelta {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+//the mempool kept in memory between snapshots, so it can be updated one transaction at a time
+//instead of refetched whole. package stats are refreshed for the direct parents and children of
+//transactions that come and go, so those of more distant relatives can lag behind
+#[derive(Debug, Default)]
+pub struct MempoolState {
+    txns: HashMap<String, MempoolTransaction>,
+    delta: MempoolDelta,
+    //txids whose package stats changed because a relative was added or removed
+    stale_txids: HashSet<String>,
+}
+
+impl MempoolState {
+    //the whole mempool, fetched once to start from
+    pub fn fetch(source: &dyn MempoolSource) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        Ok(Self::from_txns(source.mempool_txns()?))
+    }
+
+    pub fn from_txns(txns: Vec<MempoolTransaction>) -> Self {
+        MempoolState {
+            txns: txns.into_iter().map(|txn| (txn.txid.clone(), txn)).collect(),
+            ..MempoolState::default()
+        }
+    }
+
+    pub fn contains(&self, txid: &str) -> bool {
+        self.txns.contains_key(txid)
+    }
+
+    //brings the state up to date with the source's txids, looking up only the txids not seen before
+    pub fn refresh(&mut self, source: &dyn MempoolSource) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mempool_txids = source.mempool_txids()?;
+
+        let removed_txids: Vec<String> = self.txns.keys()
+            .filter(|txid| !mempool_txids.contains(*txid))
+            .cloned()
+            .collect();
+
+        for txid in &removed_txids {
+            self.remove(txid);
+        }
+
+        let new_txids: Vec<&String> = mempool_txids.iter().filter(|txid| !self.contains(txid)).collect();
+
+        if new_txids.len() > FULL_REFETCH_THRESHOLD {
+            let fetched = Self::fetch(source)?;
+            let added_txids: Vec<String> = fetched.txns.keys().filter(|txid| !self.contains(txid)).cloned().collect();
+            self.delta.added.extend(added_txids);
+            self.txns = fetched.txns;
+            self.stale_txids.clear();
+            return Ok(());
+        }
+
+        for txid in new_txids {
+            self.add(source, txid);
+        }
+
+        self.refresh_package_stats(source);
+
+        Ok(())
+    }
+
+    //looks up a newly accepted transaction. false if it is already known or has left the mempool
+    //again by the time it is looked up
+    pub fn add(&mut self, source: &dyn MempoolSource, txid: &str) -> bool {
+        if self.contains(txid) {
+            return false;
+        }
+
+        match source.mempool_entry(txid) {
+            Some(txn) => {
+                self.stale_txids.extend(txn.parent_txids.iter().cloned());
+                self.delta.added.push(txid.to_string());
+                self.txns.insert(txid.to_string(), txn);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn remove(&mut self, txid: &str) -> Option<MempoolTransaction> {
+        let txn = self.txns.remove(txid)?;
+        self.stale_txids.extend(txn.child_txids().iter().cloned());
+        self.delta.removed.push(txid.to_string());
+
+        Some(txn)
+    }
+
+    //bitcoind does not publish a removal for transactions that leave the mempool by being mined
+    pub fn remove_block_txns(&mut self, block_txids: &[String]) -> usize {
+        block_txids.iter().filter(|txid| self.remove(txid).is_some()).count()
+    }
+
+    //looks up the relatives of transactions added or removed since the last refresh again
+    pub fn refresh_package_stats(&mut self, source: &dyn MempoolSource) {
+        for txid in std::mem::take(&mut self.stale_txids) {
+            if !self.contains(&txid) {
+                continue;
+            }
+
+            //gone since if not found, which the next refresh or removal event will record
+            if let Some(txn) = source.mempool_entry(&txid) {
+                self.txns.insert(txid, txn);
+            }
+        }
+    }
+
+    pub fn take_delta(&mut self) -> MempoolDelta {
+        std::mem::take(&mut self.delta)
+    }
+
+    pub fn snapshot(&self, shape_cache: Option<&TxnShapeCache>) -> (Vec<MempoolTransaction>, HashSet<String>) {
+        let mut mempool_txns: Vec<MempoolTransaction> = self.txns.values().cloned().collect();
+
+        if let Some(shape_cache) = shape_cache {
+            shape_cache.decode(&mut mempool_txns);
+        }
+
+        let mempool_txids = self.txns.keys().cloned().collect();
+
+        (mempool_txns, mempool_txids)
+    }
+}
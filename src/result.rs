@@ -1,118 +1,1161 @@
 #![allow(unused)]
 use serde::{Serialize, Deserialize};
+use std::collections::{HashMap, HashSet};
 use std::fs::File;
 use std::io::BufWriter;
 use std::error::Error;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use chrono::Utc;
 use csv::Writer;
-use serde_json;
+use rusqlite::{params, Connection, OptionalExtension, TransactionBehavior};
+use crate::block_fidelity::BlockFidelity;
+use crate::config::Config;
+use crate::fee_rate::{FeeRate, FeeRateUnit};
+use crate::mempool_data::MempoolTransaction;
+use crate::mempool_data_subsets::{age_bucket, vsize_bucket};
+use crate::runner::TemplateComparisons;
+
+pub const RESULT_DB_FILE_NAME: &str = "result.db";
+pub const RESULT_CSV_FILE_NAME: &str = "result.csv";
+pub const RESULT_BY_AGE_CSV_FILE_NAME: &str = "result_by_age.csv";
+pub const RESULT_BY_VSIZE_CSV_FILE_NAME: &str = "result_by_vsize.csv";
+pub const RESULT_BY_SCRIPT_TYPE_CSV_FILE_NAME: &str = "result_by_script_type.csv";
+pub const RESULT_BY_PACKAGE_ROLE_CSV_FILE_NAME: &str = "result_by_package_role.csv";
+pub const RESULT_BY_FEE_BAND_CSV_FILE_NAME: &str = "result_by_fee_band.csv";
+pub const INCLUSION_CURVE_CSV_FILE_NAME: &str = "inclusion_curve.csv";
+pub const REFERENCE_FEES_CSV_FILE_NAME: &str = "reference_fees.csv";
+pub const TEMPLATE_DIFF_CSV_FILE_NAME: &str = "template_diff.csv";
+pub const BLOCK_FIDELITY_CSV_FILE_NAME: &str = "block_fidelity.csv";
+pub const SUMMARY_FILE_NAME: &str = "summary.json";
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS runs (
+        run_id TEXT PRIMARY KEY,
+        strategy_name TEXT NOT NULL,
+        duration INTEGER NOT NULL,
+        units TEXT NOT NULL,
+        started_at TEXT NOT NULL
+    );
+
+    CREATE TABLE IF NOT EXISTS snapshots (
+        snapshot_id INTEGER PRIMARY KEY AUTOINCREMENT,
+        run_id TEXT NOT NULL REFERENCES runs(run_id),
+        prev_block_height INTEGER NOT NULL,
+        prev_block_hash TEXT NOT NULL,
+        target_block_height INTEGER NOT NULL,
+        mempool_depth INTEGER NOT NULL,
+        snapshot_timestamp TEXT NOT NULL,
+        template_total_fees INTEGER NOT NULL DEFAULT 0,
+        template_total_weight INTEGER NOT NULL DEFAULT 0,
+        template_percentage_of_total_possible_fees REAL
+    );
+
+    CREATE TABLE IF NOT EXISTS estimates (
+        snapshot_id INTEGER NOT NULL REFERENCES snapshots(snapshot_id),
+        strategy_name TEXT NOT NULL,
+        mempool_fee_rate_estimate_sat_per_vb REAL
+    );
+
+    CREATE TABLE IF NOT EXISTS blocks (
+        snapshot_id INTEGER NOT NULL REFERENCES snapshots(snapshot_id),
+        target_block_height INTEGER NOT NULL,
+        target_block_hash TEXT NOT NULL,
+        target_block_txns_count INTEGER NOT NULL,
+        blocks_found_count INTEGER NOT NULL,
+        block_discovery_timestamp TEXT NOT NULL,
+        snapshot_age_ms INTEGER NOT NULL,
+        reorged INTEGER NOT NULL DEFAULT 0,
+        blocks_since_snapshot INTEGER NOT NULL DEFAULT 1
+    );
+
+    CREATE TABLE IF NOT EXISTS subset_results (
+        snapshot_id INTEGER NOT NULL REFERENCES snapshots(snapshot_id),
+        subset_index INTEGER NOT NULL,
+        subset_name TEXT NOT NULL,
+        mempool_subset_fee_rate_estimate_sat_per_vb REAL,
+        mempool_subset_txns_count INTEGER NOT NULL,
+        mempool_subset_txns_in_target_block_count INTEGER NOT NULL,
+        conditional_probability REAL,
+        mempool_subset_txns_replaced_in_target_block_count INTEGER NOT NULL,
+        conditional_probability_with_replacements REAL,
+        mempool_subset_txns_in_blocks_since_snapshot_count INTEGER NOT NULL DEFAULT 0,
+        cumulative_probability REAL
+    );
+
+    CREATE TABLE IF NOT EXISTS fee_band_results (
+        snapshot_id INTEGER NOT NULL REFERENCES snapshots(snapshot_id),
+        band_index INTEGER NOT NULL,
+        band_lower_sat_per_vb REAL NOT NULL,
+        band_upper_sat_per_vb REAL,
+        mempool_txns_count INTEGER NOT NULL,
+        mempool_txns_in_target_block_count INTEGER NOT NULL,
+        inclusion_rate REAL
+    );
+
+    CREATE TABLE IF NOT EXISTS block_fidelity (
+        snapshot_id INTEGER NOT NULL REFERENCES snapshots(snapshot_id),
+        template_txns_count INTEGER NOT NULL,
+        block_txns_count INTEGER NOT NULL,
+        mined_txns_not_in_mempool_count INTEGER NOT NULL,
+        template_txns_mined_count INTEGER NOT NULL,
+        template_txns_left_out_count INTEGER NOT NULL,
+        template_fees INTEGER NOT NULL,
+        template_weight INTEGER NOT NULL,
+        mined_txns_fees INTEGER NOT NULL,
+        mined_txns_weight INTEGER NOT NULL,
+        fee_captured REAL,
+        weight_used REAL
+    );
+
+    CREATE TABLE IF NOT EXISTS comparisons (
+        comparison_id INTEGER PRIMARY KEY AUTOINCREMENT,
+        run_id TEXT NOT NULL REFERENCES runs(run_id),
+        target_block_height INTEGER NOT NULL,
+        snapshot_timestamp TEXT NOT NULL,
+        mempool_fee_rate_estimate_sat_per_vb REAL
+    );
+
+    CREATE TABLE IF NOT EXISTS reference_fees (
+        comparison_id INTEGER NOT NULL REFERENCES comparisons(comparison_id),
+        fastest_fee_sat_per_vb REAL NOT NULL,
+        half_hour_fee_sat_per_vb REAL NOT NULL,
+        hour_fee_sat_per_vb REAL NOT NULL,
+        economy_fee_sat_per_vb REAL NOT NULL,
+        minimum_fee_sat_per_vb REAL NOT NULL,
+        projected_txns_count INTEGER,
+        projected_vsize REAL,
+        projected_total_fees INTEGER,
+        projected_median_fee_sat_per_vb REAL,
+        projected_min_fee_sat_per_vb REAL,
+        projected_max_fee_sat_per_vb REAL,
+        template_txns_count INTEGER NOT NULL,
+        template_vsize INTEGER NOT NULL,
+        template_total_fees INTEGER NOT NULL,
+        template_median_fee_sat_per_vb REAL,
+        template_min_fee_sat_per_vb REAL,
+        template_max_fee_sat_per_vb REAL,
+        projected_blocks_json TEXT NOT NULL
+    );
+
+    CREATE TABLE IF NOT EXISTS template_diffs (
+        comparison_id INTEGER NOT NULL REFERENCES comparisons(comparison_id),
+        our_txns_count INTEGER NOT NULL,
+        core_txns_count INTEGER NOT NULL,
+        common_txns_count INTEGER NOT NULL,
+        jaccard_overlap REAL NOT NULL,
+        our_fees INTEGER NOT NULL,
+        core_fees INTEGER NOT NULL,
+        fee_difference INTEGER NOT NULL,
+        our_weight INTEGER NOT NULL,
+        core_weight INTEGER NOT NULL,
+        weight_difference INTEGER NOT NULL
+    );
+
+    CREATE TABLE IF NOT EXISTS template_diff_txns (
+        comparison_id INTEGER NOT NULL REFERENCES comparisons(comparison_id),
+        txid TEXT NOT NULL,
+        only_in TEXT NOT NULL
+    );
+
+    CREATE TABLE IF NOT EXISTS txn_inclusion (
+        snapshot_id INTEGER NOT NULL REFERENCES snapshots(snapshot_id),
+        subset_name TEXT NOT NULL,
+        txid TEXT NOT NULL,
+        fee INTEGER NOT NULL,
+        weight INTEGER NOT NULL,
+        vsize INTEGER NOT NULL,
+        fee_rate_sat_per_vb REAL,
+        effective_fee_rate_sat_per_vb REAL,
+        ancestor_count INTEGER,
+        package_role TEXT NOT NULL,
+        age_seconds INTEGER NOT NULL,
+        age_bucket TEXT NOT NULL,
+        vsize_bucket TEXT NOT NULL,
+        input_script_type TEXT NOT NULL,
+        output_script_type TEXT NOT NULL,
+        op_return INTEGER,
+        inscription INTEGER,
+        included INTEGER NOT NULL,
+        replaced_by TEXT
+    );
+
+    CREATE INDEX IF NOT EXISTS snapshots_run_id ON snapshots(run_id);
+    CREATE INDEX IF NOT EXISTS blocks_snapshot_id ON blocks(snapshot_id);
+    CREATE INDEX IF NOT EXISTS subset_results_snapshot_id ON subset_results(snapshot_id);
+    CREATE INDEX IF NOT EXISTS fee_band_results_snapshot_id ON fee_band_results(snapshot_id);
+    CREATE INDEX IF NOT EXISTS block_fidelity_snapshot_id ON block_fidelity(snapshot_id);
+    CREATE INDEX IF NOT EXISTS comparisons_run_id ON comparisons(run_id);
+    CREATE INDEX IF NOT EXISTS reference_fees_comparison_id ON reference_fees(comparison_id);
+    CREATE INDEX IF NOT EXISTS template_diffs_comparison_id ON template_diffs(comparison_id);
+    CREATE INDEX IF NOT EXISTS template_diff_txns_comparison_id ON template_diff_txns(comparison_id);
+    CREATE INDEX IF NOT EXISTS txn_inclusion_snapshot_id ON txn_inclusion(snapshot_id);
+";
+
+//one row per found block and subset, joining the snapshot it was scored against and the strategy estimates
+const RESULT_QUERY: &str = "
+    SELECT r.subset_name, s.prev_block_height, s.prev_block_hash, b.target_block_height, b.target_block_hash,
+        e.mempool_fee_rate_estimate_sat_per_vb, r.mempool_subset_fee_rate_estimate_sat_per_vb, r.mempool_subset_txns_count,
+        b.target_block_txns_count, r.mempool_subset_txns_in_target_block_count,
+        r.conditional_probability, r.mempool_subset_txns_replaced_in_target_block_count, r.conditional_probability_with_replacements,
+        s.mempool_depth, b.blocks_found_count, b.block_discovery_timestamp, s.snapshot_timestamp, b.snapshot_age_ms, b.reorged,
+        b.blocks_since_snapshot, r.mempool_subset_txns_in_blocks_since_snapshot_count, r.cumulative_probability,
+        s.template_total_fees, s.template_total_weight, s.template_percentage_of_total_possible_fees
+    FROM blocks b
+    JOIN snapshots s ON s.snapshot_id = b.snapshot_id
+    JOIN estimates e ON e.snapshot_id = s.snapshot_id
+    JOIN subset_results r ON r.snapshot_id = s.snapshot_id
+    WHERE s.run_id = ?1
+    ORDER BY b.blocks_found_count, r.subset_index
+";
+
+//inclusion of the whole snapshot mempool per found block and fee band, with the strategy estimate of the snapshot
+const RESULT_BY_FEE_BAND_QUERY: &str = "
+    SELECT b.target_block_height, b.blocks_found_count, f.band_lower_sat_per_vb, f.band_upper_sat_per_vb,
+        f.mempool_txns_count, f.mempool_txns_in_target_block_count, f.inclusion_rate, e.mempool_fee_rate_estimate_sat_per_vb
+    FROM fee_band_results f
+    JOIN blocks b ON b.snapshot_id = f.snapshot_id
+    JOIN snapshots s ON s.snapshot_id = f.snapshot_id
+    JOIN estimates e ON e.snapshot_id = f.snapshot_id
+    WHERE s.run_id = ?1 AND b.reorged = 0
+    ORDER BY b.blocks_found_count, f.band_index
+";
+
+//the empirical probability of next-block inclusion per fee band over the run. pooled is over all
+//txns seen in the band, mean is over blocks, and estimates_in_band counts the blocks whose strategy estimate fell in the band
+const INCLUSION_CURVE_QUERY: &str = "
+    SELECT f.band_lower_sat_per_vb, f.band_upper_sat_per_vb, COUNT(*),
+        SUM(f.mempool_txns_count), SUM(f.mempool_txns_in_target_block_count),
+        CAST(SUM(f.mempool_txns_in_target_block_count) AS REAL) / SUM(f.mempool_txns_count),
+        AVG(f.inclusion_rate),
+        SUM(e.mempool_fee_rate_estimate_sat_per_vb >= f.band_lower_sat_per_vb
+            AND (f.band_upper_sat_per_vb IS NULL OR e.mempool_fee_rate_estimate_sat_per_vb < f.band_upper_sat_per_vb))
+    FROM fee_band_results f
+    JOIN blocks b ON b.snapshot_id = f.snapshot_id
+    JOIN snapshots s ON s.snapshot_id = f.snapshot_id
+    JOIN estimates e ON e.snapshot_id = f.snapshot_id
+    WHERE s.run_id = ?1 AND b.reorged = 0
+    GROUP BY f.band_index
+    ORDER BY f.band_index
+";
+
+//the template predicted from each found block's snapshot against the mined block
+const BLOCK_FIDELITY_QUERY: &str = "
+    SELECT b.target_block_height, b.blocks_found_count, f.template_txns_count, f.block_txns_count,
+        f.mined_txns_not_in_mempool_count, f.template_txns_mined_count, f.template_txns_left_out_count,
+        f.template_fees, f.template_weight, f.mined_txns_fees, f.mined_txns_weight, f.fee_captured, f.weight_used
+    FROM block_fidelity f
+    JOIN blocks b ON b.snapshot_id = f.snapshot_id
+    JOIN snapshots s ON s.snapshot_id = f.snapshot_id
+    WHERE s.run_id = ?1 AND b.reorged = 0
+    ORDER BY b.blocks_found_count
+";
+
+//mempool.space's fees and next projected block next to our template and the strategy estimate, per comparison.
+//blocks_found_count is null until the block the compared snapshot was waiting on is found
+const REFERENCE_FEES_QUERY: &str = "
+    SELECT c.target_block_height, c.snapshot_timestamp, b.blocks_found_count, c.mempool_fee_rate_estimate_sat_per_vb,
+        f.fastest_fee_sat_per_vb, f.half_hour_fee_sat_per_vb, f.hour_fee_sat_per_vb, f.economy_fee_sat_per_vb, f.minimum_fee_sat_per_vb,
+        f.projected_txns_count, f.projected_vsize, f.projected_total_fees,
+        f.projected_median_fee_sat_per_vb, f.projected_min_fee_sat_per_vb, f.projected_max_fee_sat_per_vb,
+        f.template_txns_count, f.template_vsize, f.template_total_fees,
+        f.template_median_fee_sat_per_vb, f.template_min_fee_sat_per_vb, f.template_max_fee_sat_per_vb
+    FROM reference_fees f
+    JOIN comparisons c ON c.comparison_id = f.comparison_id
+    LEFT JOIN (
+        SELECT b.target_block_height, b.blocks_found_count FROM blocks b
+        JOIN snapshots s ON s.snapshot_id = b.snapshot_id
+        WHERE s.run_id = ?1 AND b.reorged = 0
+    ) b ON b.target_block_height = c.target_block_height
+    WHERE c.run_id = ?1
+    ORDER BY c.comparison_id
+";
+
+//our template against getblocktemplate per comparison, with the txids unique to each side. blocks_found_count
+//is null until the block the compared snapshot was waiting on is found
+const TEMPLATE_DIFF_QUERY: &str = "
+    SELECT c.target_block_height, c.snapshot_timestamp, b.blocks_found_count, d.our_txns_count, d.core_txns_count, d.common_txns_count,
+        d.jaccard_overlap, d.our_fees, d.core_fees, d.fee_difference, d.our_weight, d.core_weight, d.weight_difference,
+        (SELECT COUNT(*) FROM template_diff_txns t WHERE t.comparison_id = d.comparison_id AND t.only_in = 'ours'),
+        (SELECT COUNT(*) FROM template_diff_txns t WHERE t.comparison_id = d.comparison_id AND t.only_in = 'core')
+    FROM template_diffs d
+    JOIN comparisons c ON c.comparison_id = d.comparison_id
+    LEFT JOIN (
+        SELECT b.target_block_height, b.blocks_found_count FROM blocks b
+        JOIN snapshots s ON s.snapshot_id = b.snapshot_id
+        WHERE s.run_id = ?1 AND b.reorged = 0
+    ) b ON b.target_block_height = c.target_block_height
+    WHERE c.run_id = ?1
+    ORDER BY c.comparison_id
+";
+
+//the txn_inclusion column each breakdown csv groups by, how its buckets are ordered, and the file it is written to
+const BREAKDOWNS: [(&str, &str, &str); 4] = [
+    ("age_bucket", "MIN(t.age_seconds)", RESULT_BY_AGE_CSV_FILE_NAME),
+    ("vsize_bucket", "MIN(t.vsize)", RESULT_BY_VSIZE_CSV_FILE_NAME),
+    ("input_script_type", "t.input_script_type", RESULT_BY_SCRIPT_TYPE_CSV_FILE_NAME),
+    ("package_role", "t.package_role", RESULT_BY_PACKAGE_ROLE_CSV_FILE_NAME),
+];
+
+//inclusion of each subset per found block and bucket
+fn breakdown_query(column: &str, order_by: &str) -> String {
+    format!("
+        SELECT t.subset_name, b.target_block_height, b.blocks_found_count, t.{column},
+            COUNT(*), SUM(t.included), AVG(t.included), SUM(t.replaced_by IS NOT NULL)
+        FROM txn_inclusion t
+        JOIN blocks b ON b.snapshot_id = t.snapshot_id
+        JOIN snapshots s ON s.snapshot_id = t.snapshot_id
+        WHERE s.run_id = ?1 AND b.reorged = 0
+        GROUP BY t.snapshot_id, t.subset_name, t.{column}
+        ORDER BY b.blocks_found_count, MIN(t.rowid), {order_by}
+    ")
+}
 
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct AnalyzerResult {
+    pub subset_name: String,
     pub prev_block_height: u32,
     pub prev_block_hash: String,
     pub target_block_height: u32,
     pub target_block_hash: String,
-    pub mempool_fee_rate_estimate: f64,
-    pub mempool_subset_fee_rate_estimate: f64,
+    pub mempool_fee_rate_estimate: FeeRate,
+    pub mempool_subset_fee_rate_estimate: FeeRate,
     pub mempool_subset_txns_count: usize,
     pub target_block_txns_count: usize,
     pub mempool_subset_txns_in_target_block_count: usize,
     pub conditional_probability: f64,
+    //subset txns not mined themselves but replaced by a mined txn spending the same outputs
+    pub mempool_subset_txns_replaced_in_target_block_count: usize,
+    pub conditional_probability_with_replacements: f64,
     pub mempool_depth: usize,
     pub blocks_found_count: usize,
     pub block_discovery_timestamp: String,
-    pub snapshot_timestamp: String
+    pub snapshot_timestamp: String,
+    //how long before the block was first seen the scored snapshot was taken
+    pub snapshot_age_ms: u64,
+    //the block was later replaced in a chain reorganization, so it does not count toward the run
+    pub reorged: bool,
+    //1 for the block the snapshot was waiting on, more when several blocks were found before the next snapshot
+    pub blocks_since_snapshot: usize,
+    //subset txns included in any of the blocks found since the snapshot, up to and including this one
+    pub mempool_subset_txns_in_blocks_since_snapshot_count: usize,
+    pub cumulative_probability: f64,
+    //metrics of the BlockBuilder template built from the snapshot, the same for every subset
+    pub template_total_fees: u64,
+    pub template_total_weight: u64,
+    //template fees over the fees of the whole snapshot mempool
+    pub template_percentage_of_total_possible_fees: f64
 }
 
-pub struct AnalyzerResultUpdate {
-    pub target_block_hash: String,
-    pub target_block_txns_count: usize,
-    pub mempool_subset_txns_in_target_block_count: usize
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum RunStatus {
+    //the run found all --duration blocks
+    Complete,
+    //the run was interrupted and only covers the blocks found so far
+    Partial
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RunSummary {
+    pub run_id: String,
+    pub status: RunStatus,
+    pub duration: usize,
+    pub blocks_found_count: usize,
+    pub units: String,
+    pub mean_mempool_fee_rate_estimate: f64,
+    pub subsets: Vec<SubsetSummary>,
+    pub finished_at: String
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SubsetSummary {
+    pub subset_name: String,
+    pub mean_mempool_subset_fee_rate_estimate: f64,
+    pub mean_conditional_probability: f64,
+    pub mean_conditional_probability_with_replacements: f64
+}
+
+//inclusion of the snapshot mempool txns whose effective fee rate is in [lower, upper)
+pub struct FeeBandResult {
+    pub lower: FeeRate,
+    pub upper: FeeRate,
+    pub mempool_txns_count: usize,
+    pub mempool_txns_in_target_block_count: usize
+}
+
+impl FeeBandResult {
+    pub fn inclusion_rate(&self) -> f64 {
+        self.mempool_txns_in_target_block_count as f64 / self.mempool_txns_count as f64
+    }
+}
+
+//sqlite has no infinity, so the open-ended top band is stored with a NULL upper bound
+fn null_if_infinite(value: f64) -> Option<f64> {
+    if value.is_finite() { Some(value) } else { None }
+}
+
+//a found block scored for one subset, with the subset's txns for the per-txn inclusion rows
+pub struct ScoredSubset {
+    pub result: AnalyzerResult,
+    pub subset_txns: Vec<MempoolTransaction>,
+    //subset txids mapped to the mined txn that replaced them
+    pub replacements: HashMap<String, String>
+}
+
+//mean over the values that are defined; NaN (null in the summary) if there are none
+fn mean(values: impl Iterator<Item = f64>) -> f64 {
+    let (sum, count) = values.filter(|v| !v.is_nan()).fold((0.0, 0), |(sum, count), v| (sum + v, count + 1));
+    sum / count as f64
 }
 
 pub trait AnalyzerResultProcessor {
-    fn save_intermediate_result(&self, result: Vec<AnalyzerResult>) -> Result<(), Box<dyn Error>>;
-    fn update_intermediate_result(&self, prev_block_height: u32, result_update: AnalyzerResultUpdate) -> Result<(), Box<dyn Error>>;
-    fn load_intermediate_result(&self) -> Result<Vec<AnalyzerResult>, Box<dyn Error>>;
+    fn save_intermediate_result(
+        &mut self,
+        scored_subsets: &[ScoredSubset],
+        fee_bands: &[FeeBandResult],
+        fidelity: &BlockFidelity,
+        txids_in_block: &HashSet<&String>,
+        snapshot_time: u64
+    ) -> Result<bool, Box<dyn Error + Send + Sync>>;
+    //appends the references compared against the template of the snapshot the result was estimated from
+    fn save_comparisons(&mut self, snapshot_result: &AnalyzerResult, comparisons: &TemplateComparisons) -> Result<(), Box<dyn Error + Send + Sync>>;
+    fn load_intermediate_result(&self) -> Result<Vec<AnalyzerResult>, Box<dyn Error + Send + Sync>>;
+    //flags the results scored against blocks no longer in the best chain. returns how many blocks were flagged
+    fn mark_reorged(&mut self, block_hashes: &[String]) -> Result<usize, Box<dyn Error + Send + Sync>>;
     fn result_exists(&self) -> bool;
-    fn save_final_result(&self, intermediate_result: Vec<AnalyzerResult>) -> Result<(), Box<dyn Error>>;
+    fn save_final_result(&self, status: RunStatus) -> Result<(), Box<dyn Error + Send + Sync>>;
 }
 
-impl AnalyzerResultProcessor for AnalyzerResult {
-    fn save_intermediate_result(&self, result: Vec<AnalyzerResult>) -> Result<(), Box<dyn Error>> {
-        let file = File::create("result.json")?;
-        let writer = BufWriter::new(file);
-        serde_json::to_writer_pretty(writer, &result)?;
+//sqlite stores NaN as NULL, e.g. the conditional probability of an empty subset
+fn nan_if_null(value: Option<f64>) -> f64 {
+    value.unwrap_or(f64::NAN)
+}
 
-        println!("Intermediate result saved in result.json");
+pub struct ResultStore {
+    connection: Connection,
+    run_dir: PathBuf,
+    run_id: String,
+    strategy_name: String,
+    units: FeeRateUnit
+}
 
-        Ok(())
+impl ResultStore {
+    fn connect(run_dir: &Path) -> Result<Connection, Box<dyn Error + Send + Sync>> {
+        let connection = Connection::open(run_dir.join(RESULT_DB_FILE_NAME))?;
+        //spawned analysis tasks write through their own connections
+        connection.busy_timeout(Duration::from_secs(10))?;
+        connection.execute_batch(SCHEMA)?;
+        Ok(connection)
     }
 
-    fn update_intermediate_result(&self, prev_block_height: u32, result_update: AnalyzerResultUpdate) -> Result<(), Box<dyn Error>> {
-        let mut result = self.load_intermediate_result()?;
+    pub fn create_run(run_dir: &Path, run_id: &str, config: &Config, started_at: &str) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let connection = Self::connect(run_dir)?;
+
+        connection.execute(
+            "INSERT INTO runs (run_id, strategy_name, duration, units, started_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![run_id, config.strategy_name, config.duration as i64, config.units.column_suffix(), started_at],
+        )?;
+
+        Ok(ResultStore {
+            connection,
+            run_dir: run_dir.to_path_buf(),
+            run_id: run_id.to_string(),
+            strategy_name: config.strategy_name.clone(),
+            units: config.units
+        })
+    }
+
+    //a fee rate csv column carrying the run's unit as a suffix, e.g. mempool_fee_rate_estimate_sat_per_vb
+    fn fee_rate_column(&self, name: &str) -> String {
+        format!("{}_{}", name, self.units.column_suffix())
+    }
+
+    //a fee rate stored in sat/vB, as every rate in the database is, converted to the run's unit
+    fn in_units(&self, sat_per_vb: f64) -> f64 {
+        FeeRate::from_sat_per_vb(sat_per_vb).in_units(self.units)
+    }
+
+    fn save_summary(&self, status: RunStatus, result: &[AnalyzerResult]) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let duration: i64 = self.connection.query_row(
+            "SELECT duration FROM runs WHERE run_id = ?1",
+            params![self.run_id],
+            |row| row.get(0),
+        )?;
+
+        let mut subset_names: Vec<&String> = vec![];
+        for r in result {
+            if !subset_names.contains(&&r.subset_name) {
+                subset_names.push(&r.subset_name);
+            }
+        }
 
-        for i in 0..result.len() {
-            if result[i].target_block_height == prev_block_height {
-                result[i].target_block_hash = result_update.target_block_hash.clone();
-                result[i].target_block_txns_count = result_update.target_block_txns_count;
-                result[i].mempool_subset_txns_in_target_block_count = result_update.mempool_subset_txns_in_target_block_count;
-                result[i].conditional_probability = result_update.mempool_subset_txns_in_target_block_count as f64 / result[i].mempool_subset_txns_count as f64;
+        let subsets = subset_names.into_iter().map(|subset_name| {
+            let subset_result: Vec<&AnalyzerResult> = result.iter().filter(|r| &r.subset_name == subset_name).collect();
+            SubsetSummary {
+                subset_name: subset_name.clone(),
+                mean_mempool_subset_fee_rate_estimate: mean(subset_result.iter().map(|r| r.mempool_subset_fee_rate_estimate.in_units(self.units))),
+                mean_conditional_probability: mean(subset_result.iter().map(|r| r.conditional_probability)),
+                mean_conditional_probability_with_replacements: mean(subset_result.iter().map(|r| r.conditional_probability_with_replacements))
             }
+        }).collect();
+
+        //the overall estimate is the same for every subset of a block, so one row per block is enough
+        let first_subset = result.first().map(|r| r.subset_name.clone()).unwrap_or_default();
+
+        let summary = RunSummary {
+            run_id: self.run_id.clone(),
+            status,
+            duration: duration as usize,
+            blocks_found_count: result.last().map(|r| r.blocks_found_count).unwrap_or(0),
+            units: self.units.label().to_string(),
+            mean_mempool_fee_rate_estimate: mean(result.iter().filter(|r| r.subset_name == first_subset).map(|r| r.mempool_fee_rate_estimate.in_units(self.units))),
+            subsets,
+            finished_at: Utc::now().format("%Y-%m-%d %H:%M:%S%z").to_string()
         };
 
-        self.save_intermediate_result(result)?;
+        let file = File::create(self.run_dir.join(SUMMARY_FILE_NAME))?;
+        serde_json::to_writer_pretty(BufWriter::new(file), &summary)?;
+
+        println!(
+            "Run {} is {}: {} of {} blocks found",
+            summary.run_id,
+            if status == RunStatus::Complete { "complete" } else { "partial" },
+            summary.blocks_found_count,
+            summary.duration
+        );
+
+        for subset in &summary.subsets {
+            println!("  {}: mean conditional probability {:.4}", subset.subset_name, subset.mean_conditional_probability);
+        }
 
         Ok(())
     }
 
-    fn load_intermediate_result(&self) -> Result<Vec<AnalyzerResult>, Box<dyn Error>> {
-        let mut result = vec![];
-        let file = File::open("result.json")?;
-        let reader = std::io::BufReader::new(file);
-        let deserialized_data: Vec<AnalyzerResult> = serde_json::from_reader(reader)?;
+    fn save_breakdown(&self, column: &str, order_by: &str, file_name: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let file = File::create(self.run_dir.join(file_name))?;
+        let mut wtr = Writer::from_writer(file);
+
+        wtr.write_record([
+            "subset_name", "target_block_height", "blocks_found_count", column,
+            "mempool_subset_txns_count", "mempool_subset_txns_in_target_block_count", "inclusion_probability",
+            "mempool_subset_txns_replaced_in_target_block_count",
+        ])?;
+
+        let mut statement = self.connection.prepare(&breakdown_query(column, order_by))?;
+        let mut rows = statement.query(params![self.run_id])?;
+
+        while let Some(row) = rows.next()? {
+            wtr.write_record([
+                row.get::<_, String>(0)?,
+                row.get::<_, u32>(1)?.to_string(),
+                row.get::<_, i64>(2)?.to_string(),
+                row.get::<_, String>(3)?,
+                row.get::<_, i64>(4)?.to_string(),
+                row.get::<_, i64>(5)?.to_string(),
+                row.get::<_, f64>(6)?.to_string(),
+                row.get::<_, i64>(7)?.to_string(),
+            ])?;
+        }
+
+        wtr.flush()?;
+
+        Ok(())
+    }
+
+    //the open-ended top band is written with an empty upper bound
+    fn save_fee_bands(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let file = File::create(self.run_dir.join(RESULT_BY_FEE_BAND_CSV_FILE_NAME))?;
+        let mut wtr = Writer::from_writer(file);
+
+        wtr.write_record([
+            "target_block_height", "blocks_found_count", &self.fee_rate_column("band_lower"), &self.fee_rate_column("band_upper"),
+            "mempool_txns_count", "mempool_txns_in_target_block_count", "inclusion_rate",
+            &self.fee_rate_column("mempool_fee_rate_estimate"),
+        ])?;
+
+        let mut statement = self.connection.prepare(RESULT_BY_FEE_BAND_QUERY)?;
+        let mut rows = statement.query(params![self.run_id])?;
+
+        while let Some(row) = rows.next()? {
+            wtr.write_record([
+                row.get::<_, u32>(0)?.to_string(),
+                row.get::<_, i64>(1)?.to_string(),
+                self.in_units(row.get(2)?).to_string(),
+                row.get::<_, Option<f64>>(3)?.map(|upper| self.in_units(upper).to_string()).unwrap_or_default(),
+                row.get::<_, i64>(4)?.to_string(),
+                row.get::<_, i64>(5)?.to_string(),
+                nan_if_null(row.get(6)?).to_string(),
+                self.in_units(nan_if_null(row.get(7)?)).to_string(),
+            ])?;
+        }
+
+        wtr.flush()?;
+
+        let file = File::create(self.run_dir.join(INCLUSION_CURVE_CSV_FILE_NAME))?;
+        let mut wtr = Writer::from_writer(file);
+
+        wtr.write_record([
+            &self.fee_rate_column("band_lower"), &self.fee_rate_column("band_upper"), "blocks_found_count",
+            "mempool_txns_count", "mempool_txns_in_target_block_count", "inclusion_probability",
+            "mean_inclusion_rate", "estimates_in_band_count",
+        ])?;
+
+        let mut statement = self.connection.prepare(INCLUSION_CURVE_QUERY)?;
+        let mut rows = statement.query(params![self.run_id])?;
+
+        while let Some(row) = rows.next()? {
+            wtr.write_record([
+                self.in_units(row.get(0)?).to_string(),
+                row.get::<_, Option<f64>>(1)?.map(|upper| self.in_units(upper).to_string()).unwrap_or_default(),
+                row.get::<_, i64>(2)?.to_string(),
+                row.get::<_, i64>(3)?.to_string(),
+                row.get::<_, i64>(4)?.to_string(),
+                nan_if_null(row.get(5)?).to_string(),
+                nan_if_null(row.get(6)?).to_string(),
+                row.get::<_, i64>(7)?.to_string(),
+            ])?;
+        }
+
+        wtr.flush()?;
+
+        Ok(())
+    }
+
+    fn save_block_fidelity(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let file = File::create(self.run_dir.join(BLOCK_FIDELITY_CSV_FILE_NAME))?;
+        let mut wtr = Writer::from_writer(file);
+
+        wtr.write_record([
+            "target_block_height", "blocks_found_count", "template_txns_count", "block_txns_count",
+            "mined_txns_not_in_mempool_count", "template_txns_mined_count", "template_txns_left_out_count",
+            "template_fees", "template_weight", "mined_txns_fees", "mined_txns_weight", "fee_captured", "weight_used",
+        ])?;
+
+        let mut statement = self.connection.prepare(BLOCK_FIDELITY_QUERY)?;
+        let mut rows = statement.query(params![self.run_id])?;
+
+        while let Some(row) = rows.next()? {
+            wtr.write_record([
+                row.get::<_, u32>(0)?.to_string(),
+                row.get::<_, i64>(1)?.to_string(),
+                row.get::<_, i64>(2)?.to_string(),
+                row.get::<_, i64>(3)?.to_string(),
+                row.get::<_, i64>(4)?.to_string(),
+                row.get::<_, i64>(5)?.to_string(),
+                row.get::<_, i64>(6)?.to_string(),
+                row.get::<_, i64>(7)?.to_string(),
+                row.get::<_, i64>(8)?.to_string(),
+                row.get::<_, i64>(9)?.to_string(),
+                row.get::<_, i64>(10)?.to_string(),
+                nan_if_null(row.get(11)?).to_string(),
+                nan_if_null(row.get(12)?).to_string(),
+            ])?;
+        }
+
+        wtr.flush()?;
+
+        Ok(())
+    }
+
+    //a csv row per comparison; no file unless the run used --mempool-space. the projected block columns
+    //are empty when mempool.space published none, e.g. for an empty mempool
+    fn save_reference_fees(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let has_reference_fees = self.connection.query_row(
+            "SELECT 1 FROM reference_fees f JOIN comparisons c ON c.comparison_id = f.comparison_id WHERE c.run_id = ?1 LIMIT 1",
+            params![self.run_id],
+            |_| Ok(()),
+        ).optional()?.is_some();
+
+        if !has_reference_fees {
+            return Ok(());
+        }
+
+        let file = File::create(self.run_dir.join(REFERENCE_FEES_CSV_FILE_NAME))?;
+        let mut wtr = Writer::from_writer(file);
+
+        wtr.write_record([
+            "target_block_height", "snapshot_timestamp", "blocks_found_count", &self.fee_rate_column("mempool_fee_rate_estimate"),
+            &self.fee_rate_column("fastest_fee"), &self.fee_rate_column("half_hour_fee"), &self.fee_rate_column("hour_fee"),
+            &self.fee_rate_column("economy_fee"), &self.fee_rate_column("minimum_fee"),
+            "projected_txns_count", "projected_vsize", "projected_total_fees",
+            &self.fee_rate_column("projected_median_fee"), &self.fee_rate_column("projected_min_fee"), &self.fee_rate_column("projected_max_fee"),
+            "template_txns_count", "template_vsize", "template_total_fees",
+            &self.fee_rate_column("template_median_fee"), &self.fee_rate_column("template_min_fee"), &self.fee_rate_column("template_max_fee"),
+        ])?;
+
+        let mut statement = self.connection.prepare(REFERENCE_FEES_QUERY)?;
+        let mut rows = statement.query(params![self.run_id])?;
+
+        while let Some(row) = rows.next()? {
+            wtr.write_record([
+                row.get::<_, u32>(0)?.to_string(),
+                row.get::<_, String>(1)?,
+                row.get::<_, Option<i64>>(2)?.map(|value| value.to_string()).unwrap_or_default(),
+                self.in_units(nan_if_null(row.get(3)?)).to_string(),
+                self.in_units(row.get(4)?).to_string(),
+                self.in_units(row.get(5)?).to_string(),
+                self.in_units(row.get(6)?).to_string(),
+                self.in_units(row.get(7)?).to_string(),
+                self.in_units(row.get(8)?).to_string(),
+                row.get::<_, Option<i64>>(9)?.map(|value| value.to_string()).unwrap_or_default(),
+                row.get::<_, Option<f64>>(10)?.map(|value| value.to_string()).unwrap_or_default(),
+                row.get::<_, Option<i64>>(11)?.map(|value| value.to_string()).unwrap_or_default(),
+                row.get::<_, Option<f64>>(12)?.map(|value| self.in_units(value).to_string()).unwrap_or_default(),
+                row.get::<_, Option<f64>>(13)?.map(|value| self.in_units(value).to_string()).unwrap_or_default(),
+                row.get::<_, Option<f64>>(14)?.map(|value| self.in_units(value).to_string()).unwrap_or_default(),
+                row.get::<_, i64>(15)?.to_string(),
+                row.get::<_, i64>(16)?.to_string(),
+                row.get::<_, i64>(17)?.to_string(),
+                self.in_units(nan_if_null(row.get(18)?)).to_string(),
+                self.in_units(nan_if_null(row.get(19)?)).to_string(),
+                self.in_units(nan_if_null(row.get(20)?)).to_string(),
+            ])?;
+        }
+
+        wtr.flush()?;
+
+        Ok(())
+    }
+
+    //a csv row per comparison with a diff; no file unless the run used --compare-block-template. the
+    //unique txids themselves are in the template_diff_txns table
+    fn save_template_diffs(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let has_template_diffs = self.connection.query_row(
+            "SELECT 1 FROM template_diffs d JOIN comparisons c ON c.comparison_id = d.comparison_id WHERE c.run_id = ?1 LIMIT 1",
+            params![self.run_id],
+            |_| Ok(()),
+        ).optional()?.is_some();
+
+        if !has_template_diffs {
+            return Ok(());
+        }
+
+        let file = File::create(self.run_dir.join(TEMPLATE_DIFF_CSV_FILE_NAME))?;
+        let mut wtr = Writer::from_writer(file);
+
+        wtr.write_record([
+            "target_block_height", "snapshot_timestamp", "blocks_found_count", "our_txns_count", "core_txns_count", "common_txns_count", "jaccard_overlap",
+            "our_fees", "core_fees", "fee_difference", "our_weight", "core_weight", "weight_difference",
+            "only_in_ours_count", "only_in_core_count",
+        ])?;
+
+        let mut statement = self.connection.prepare(TEMPLATE_DIFF_QUERY)?;
+        let mut rows = statement.query(params![self.run_id])?;
+
+        while let Some(row) = rows.next()? {
+            wtr.write_record([
+                row.get::<_, u32>(0)?.to_string(),
+                row.get::<_, String>(1)?,
+                row.get::<_, Option<i64>>(2)?.map(|value| value.to_string()).unwrap_or_default(),
+                row.get::<_, i64>(3)?.to_string(),
+                row.get::<_, i64>(4)?.to_string(),
+                row.get::<_, i64>(5)?.to_string(),
+                row.get::<_, f64>(6)?.to_string(),
+                row.get::<_, i64>(7)?.to_string(),
+                row.get::<_, i64>(8)?.to_string(),
+                row.get::<_, i64>(9)?.to_string(),
+                row.get::<_, i64>(10)?.to_string(),
+                row.get::<_, i64>(11)?.to_string(),
+                row.get::<_, i64>(12)?.to_string(),
+                row.get::<_, i64>(13)?.to_string(),
+                row.get::<_, i64>(14)?.to_string(),
+            ])?;
+        }
+
+        wtr.flush()?;
+
+        Ok(())
+    }
+
+    pub fn open(run_dir: &Path, run_id: &str) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let connection = Self::connect(run_dir)?;
+
+        let (strategy_name, units): (String, String) = connection.query_row(
+            "SELECT strategy_name, units FROM runs WHERE run_id = ?1",
+            params![run_id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        let units = FeeRateUnit::from_column_suffix(&units).unwrap_or_default();
+
+        Ok(ResultStore {
+            connection,
+            run_dir: run_dir.to_path_buf(),
+            run_id: run_id.to_string(),
+            strategy_name,
+            units
+        })
+    }
+}
+
+impl AnalyzerResultProcessor for ResultStore {
+    //appends the snapshot, its estimates, the found block, each subset's result and per-txn inclusion in one transaction.
+    //returns false if the block was already recorded and not since flagged as reorged.
+    //the snapshot and block are taken from the first subset, as they are the same for all of them.
+    //txn ages are measured at snapshot_time, the unix time the scored mempool was fetched
+    fn save_intermediate_result(
+        &mut self,
+        scored_subsets: &[ScoredSubset],
+        fee_bands: &[FeeBandResult],
+        fidelity: &BlockFidelity,
+        txids_in_block: &HashSet<&String>,
+        snapshot_time: u64
+    ) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let result = match scored_subsets.first() {
+            Some(scored_subset) => &scored_subset.result,
+            None => return Ok(false),
+        };
+
+        let run_id = self.run_id.clone();
+        let tx = self.connection.transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+        let item_already_exists = tx.query_row(
+            "SELECT 1 FROM blocks b JOIN snapshots s ON s.snapshot_id = b.snapshot_id WHERE s.run_id = ?1 AND b.target_block_hash = ?2 AND b.reorged = 0",
+            params![run_id, result.target_block_hash],
+            |_| Ok(()),
+        ).optional()?.is_some();
+
+        if item_already_exists {
+            return Ok(false);
+        }
+
+        tx.execute(
+            "INSERT INTO snapshots (run_id, prev_block_height, prev_block_hash, target_block_height, mempool_depth, snapshot_timestamp,
+            template_total_fees, template_total_weight, template_percentage_of_total_possible_fees)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            params![
+                run_id,
+                result.prev_block_height,
+                result.prev_block_hash,
+                result.target_block_height,
+                result.mempool_depth as i64,
+                result.snapshot_timestamp,
+                result.template_total_fees as i64,
+                result.template_total_weight as i64,
+                result.template_percentage_of_total_possible_fees
+            ],
+        )?;
+        let snapshot_id = tx.last_insert_rowid();
+
+        tx.execute(
+            "INSERT INTO estimates (snapshot_id, strategy_name, mempool_fee_rate_estimate_sat_per_vb) VALUES (?1, ?2, ?3)",
+            params![snapshot_id, self.strategy_name, result.mempool_fee_rate_estimate.sat_per_vb()],
+        )?;
+
+        tx.execute(
+            "INSERT INTO blocks (snapshot_id, target_block_height, target_block_hash, target_block_txns_count, blocks_found_count, block_discovery_timestamp, snapshot_age_ms,
+            blocks_since_snapshot)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![
+                snapshot_id,
+                result.target_block_height,
+                result.target_block_hash,
+                result.target_block_txns_count as i64,
+                result.blocks_found_count as i64,
+                result.block_discovery_timestamp,
+                result.snapshot_age_ms as i64,
+                result.blocks_since_snapshot as i64
+            ],
+        )?;
+
+        {
+            let mut insert_fee_band = tx.prepare(
+                "INSERT INTO fee_band_results (snapshot_id, band_index, band_lower_sat_per_vb, band_upper_sat_per_vb, mempool_txns_count, mempool_txns_in_target_block_count, inclusion_rate)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)"
+            )?;
+
+            for (band_index, fee_band) in fee_bands.iter().enumerate() {
+                insert_fee_band.execute(params![
+                    snapshot_id,
+                    band_index as i64,
+                    fee_band.lower.sat_per_vb(),
+                    null_if_infinite(fee_band.upper.sat_per_vb()),
+                    fee_band.mempool_txns_count as i64,
+                    fee_band.mempool_txns_in_target_block_count as i64,
+                    fee_band.inclusion_rate()
+                ])?;
+            }
+        }
+
+        tx.execute(
+            "INSERT INTO block_fidelity (snapshot_id, template_txns_count, block_txns_count, mined_txns_not_in_mempool_count, template_txns_mined_count,
+            template_txns_left_out_count, template_fees, template_weight, mined_txns_fees, mined_txns_weight, fee_captured, weight_used)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+            params![
+                snapshot_id,
+                fidelity.template_txns_count as i64,
+                fidelity.block_txns_count as i64,
+                fidelity.mined_txns_not_in_mempool_count as i64,
+                fidelity.template_txns_mined_count as i64,
+                fidelity.template_txns_left_out_count as i64,
+                fidelity.template_fees as i64,
+                fidelity.template_weight as i64,
+                fidelity.mined_txns_fees as i64,
+                fidelity.mined_txns_weight as i64,
+                fidelity.fee_captured,
+                fidelity.weight_used
+            ],
+        )?;
+
+        {
+            let mut insert_subset_result = tx.prepare(
+                "INSERT INTO subset_results (snapshot_id, subset_index, subset_name, mempool_subset_fee_rate_estimate_sat_per_vb, mempool_subset_txns_count,
+                mempool_subset_txns_in_target_block_count, conditional_probability, mempool_subset_txns_replaced_in_target_block_count, conditional_probability_with_replacements,
+                mempool_subset_txns_in_blocks_since_snapshot_count, cumulative_probability)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)"
+            )?;
+
+            let mut insert_txn = tx.prepare(
+                "INSERT INTO txn_inclusion (snapshot_id, subset_name, txid, fee, weight, vsize, fee_rate_sat_per_vb, effective_fee_rate_sat_per_vb, ancestor_count, package_role,
+                age_seconds, age_bucket, vsize_bucket, input_script_type, output_script_type, op_return, inscription, included, replaced_by)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19)"
+            )?;
+
+            for (subset_index, scored_subset) in scored_subsets.iter().enumerate() {
+                let subset_result = &scored_subset.result;
+
+                insert_subset_result.execute(params![
+                    snapshot_id,
+                    subset_index as i64,
+                    subset_result.subset_name,
+                    subset_result.mempool_subset_fee_rate_estimate.sat_per_vb(),
+                    subset_result.mempool_subset_txns_count as i64,
+                    subset_result.mempool_subset_txns_in_target_block_count as i64,
+                    subset_result.conditional_probability,
+                    subset_result.mempool_subset_txns_replaced_in_target_block_count as i64,
+                    subset_result.conditional_probability_with_replacements,
+                    subset_result.mempool_subset_txns_in_blocks_since_snapshot_count as i64,
+                    subset_result.cumulative_probability
+                ])?;
+
+                for txn in &scored_subset.subset_txns {
+                    let age = txn.age(snapshot_time);
+                    insert_txn.execute(params![
+                        snapshot_id,
+                        subset_result.subset_name,
+                        txn.txid,
+                        txn.fee as i64,
+                        txn.weight as i64,
+                        txn.vsize,
+                        txn.fee_rate.sat_per_vb(),
+                        txn.effective_fee_rate().sat_per_vb(),
+                        txn.package.as_ref().map(|package| package.ancestor_count),
+                        txn.package_role(),
+                        age as i64,
+                        age_bucket(age),
+                        vsize_bucket(txn.vsize),
+                        txn.shape.as_ref().map_or("undecoded", |s| s.input_script_type().label()),
+                        txn.shape.as_ref().map_or("undecoded", |s| s.output_script_type().label()),
+                        txn.shape.as_ref().map(|s| s.has_op_return),
+                        txn.shape.as_ref().map(|s| s.has_inscription),
+                        txids_in_block.contains(&txn.txid),
+                        scored_subset.replacements.get(&txn.txid)
+                    ])?;
+                }
+            }
+        }
+
+        tx.commit()?;
+
+        println!("Intermediate result saved in {}", self.run_dir.join(RESULT_DB_FILE_NAME).display());
+
+        Ok(true)
+    }
+
+    fn save_comparisons(&mut self, snapshot_result: &AnalyzerResult, comparisons: &TemplateComparisons) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let tx = self.connection.transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+        tx.execute(
+            "INSERT INTO comparisons (run_id, target_block_height, snapshot_timestamp, mempool_fee_rate_estimate_sat_per_vb) VALUES (?1, ?2, ?3, ?4)",
+            params![
+                self.run_id,
+                snapshot_result.target_block_height,
+                snapshot_result.snapshot_timestamp,
+                snapshot_result.mempool_fee_rate_estimate.sat_per_vb()
+            ],
+        )?;
+        let comparison_id = tx.last_insert_rowid();
+
+        if let Some(fee_comparison) = &comparisons.fee_comparison {
+            let recommended = &fee_comparison.recommended;
+            let projected_block = fee_comparison.next_projected_block();
+            let template = &fee_comparison.template;
 
-        for item in deserialized_data {
-            result.push(item);
+            tx.execute(
+                "INSERT INTO reference_fees (comparison_id, fastest_fee_sat_per_vb, half_hour_fee_sat_per_vb, hour_fee_sat_per_vb, economy_fee_sat_per_vb, minimum_fee_sat_per_vb,
+                projected_txns_count, projected_vsize, projected_total_fees, projected_median_fee_sat_per_vb, projected_min_fee_sat_per_vb, projected_max_fee_sat_per_vb,
+                template_txns_count, template_vsize, template_total_fees, template_median_fee_sat_per_vb, template_min_fee_sat_per_vb, template_max_fee_sat_per_vb,
+                projected_blocks_json)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19)",
+                params![
+                    comparison_id,
+                    recommended.fastest_fee,
+                    recommended.half_hour_fee,
+                    recommended.hour_fee,
+                    recommended.economy_fee,
+                    recommended.minimum_fee,
+                    projected_block.map(|block| block.n_tx as i64),
+                    projected_block.map(|block| block.block_vsize),
+                    projected_block.map(|block| block.total_fees as i64),
+                    projected_block.map(|block| block.median_fee),
+                    projected_block.and_then(|block| block.min_fee()),
+                    projected_block.and_then(|block| block.max_fee()),
+                    template.txns_count as i64,
+                    template.vsize as i64,
+                    template.total_fees as i64,
+                    template.median_fee_rate.sat_per_vb(),
+                    template.min_fee_rate.sat_per_vb(),
+                    template.max_fee_rate.sat_per_vb(),
+                    serde_json::to_string(&fee_comparison.projected_blocks)?
+                ],
+            )?;
+        }
+
+        if let Some(template_diff) = &comparisons.template_diff {
+            tx.execute(
+                "INSERT INTO template_diffs (comparison_id, our_txns_count, core_txns_count, common_txns_count, jaccard_overlap,
+                our_fees, core_fees, fee_difference, our_weight, core_weight, weight_difference)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+                params![
+                    comparison_id,
+                    template_diff.our_txns_count as i64,
+                    template_diff.core_txns_count as i64,
+                    template_diff.common_txns_count as i64,
+                    template_diff.jaccard_overlap,
+                    template_diff.our_fees as i64,
+                    template_diff.core_fees as i64,
+                    template_diff.fee_difference,
+                    template_diff.our_weight as i64,
+                    template_diff.core_weight as i64,
+                    template_diff.weight_difference
+                ],
+            )?;
+
+            let mut insert_diff_txn = tx.prepare("INSERT INTO template_diff_txns (comparison_id, txid, only_in) VALUES (?1, ?2, ?3)")?;
+
+            for txid in &template_diff.only_in_ours {
+                insert_diff_txn.execute(params![comparison_id, txid, "ours"])?;
+            }
+
+            for txid in &template_diff.only_in_core {
+                insert_diff_txn.execute(params![comparison_id, txid, "core"])?;
+            }
+        }
+
+        tx.commit()?;
+
+        Ok(())
+    }
+
+    fn load_intermediate_result(&self) -> Result<Vec<AnalyzerResult>, Box<dyn Error + Send + Sync>> {
+        let mut statement = self.connection.prepare(RESULT_QUERY)?;
+
+        let rows = statement.query_map(params![self.run_id], |row| {
+            Ok(AnalyzerResult {
+                subset_name: row.get(0)?,
+                prev_block_height: row.get(1)?,
+                prev_block_hash: row.get(2)?,
+                target_block_height: row.get(3)?,
+                target_block_hash: row.get(4)?,
+                mempool_fee_rate_estimate: FeeRate::from_sat_per_vb(nan_if_null(row.get(5)?)),
+                mempool_subset_fee_rate_estimate: FeeRate::from_sat_per_vb(nan_if_null(row.get(6)?)),
+                mempool_subset_txns_count: row.get::<_, i64>(7)? as usize,
+                target_block_txns_count: row.get::<_, i64>(8)? as usize,
+                mempool_subset_txns_in_target_block_count: row.get::<_, i64>(9)? as usize,
+                conditional_probability: nan_if_null(row.get(10)?),
+                mempool_subset_txns_replaced_in_target_block_count: row.get::<_, i64>(11)? as usize,
+                conditional_probability_with_replacements: nan_if_null(row.get(12)?),
+                mempool_depth: row.get::<_, i64>(13)? as usize,
+                blocks_found_count: row.get::<_, i64>(14)? as usize,
+                block_discovery_timestamp: row.get(15)?,
+                snapshot_timestamp: row.get(16)?,
+                snapshot_age_ms: row.get::<_, i64>(17)? as u64,
+                reorged: row.get(18)?,
+                blocks_since_snapshot: row.get::<_, i64>(19)? as usize,
+                mempool_subset_txns_in_blocks_since_snapshot_count: row.get::<_, i64>(20)? as usize,
+                cumulative_probability: nan_if_null(row.get(21)?),
+                template_total_fees: row.get::<_, i64>(22)? as u64,
+                template_total_weight: row.get::<_, i64>(23)? as u64,
+                template_percentage_of_total_possible_fees: nan_if_null(row.get(24)?)
+            })
+        })?;
+
+        let mut result = vec![];
+        for item in rows {
+            result.push(item?);
         }
 
         Ok(result)
     }
 
+    fn mark_reorged(&mut self, block_hashes: &[String]) -> Result<usize, Box<dyn Error + Send + Sync>> {
+        let mut reorged_count = 0;
+
+        for block_hash in block_hashes {
+            reorged_count += self.connection.execute(
+                "UPDATE blocks SET reorged = 1
+                WHERE target_block_hash = ?1 AND reorged = 0
+                    AND snapshot_id IN (SELECT snapshot_id FROM snapshots WHERE run_id = ?2)",
+                params![block_hash, self.run_id],
+            )?;
+        }
+
+        Ok(reorged_count)
+    }
+
     fn result_exists(&self) -> bool {
-        Path::new("result.json").exists()
+        self.connection.query_row(
+            "SELECT 1 FROM blocks b JOIN snapshots s ON s.snapshot_id = b.snapshot_id WHERE s.run_id = ?1 LIMIT 1",
+            params![self.run_id],
+            |_| Ok(()),
+        ).optional().map(|row| row.is_some()).unwrap_or(false)
     }
 
-    fn save_final_result(&self, intermediate_result: Vec<AnalyzerResult>) -> Result<(), Box<dyn Error>> {
+    //writes the csv and summary for the blocks found so far; a partial status marks an interrupted run
+    fn save_final_result(&self, status: RunStatus) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let result = self.load_intermediate_result()?;
 
-        let file = File::create("result.csv")?;
+        let file = File::create(self.run_dir.join(RESULT_CSV_FILE_NAME))?;
         let mut wtr = Writer::from_writer(file);
 
         wtr.write_record([
-            "prev_block_height", "prev_block_hash", "target_block_height", "target_block_hash",
-            "mempool_fee_rate_estimate", "mempool_subset_fee_rate_estimate", "mempool_subset_txns_count", 
-            "target_block_txns_count", "mempool_subset_txns_in_target_block_count", 
-            "conditional_probability", "mempool_depth", "blocks_found_count", 
-            "block_discovery_timestamp", "snapshot_timestamp",
+            "subset_name", "prev_block_height", "prev_block_hash", "target_block_height", "target_block_hash",
+            &self.fee_rate_column("mempool_fee_rate_estimate"), &self.fee_rate_column("mempool_subset_fee_rate_estimate"), "mempool_subset_txns_count",
+            "target_block_txns_count", "mempool_subset_txns_in_target_block_count",
+            "conditional_probability", "mempool_subset_txns_replaced_in_target_block_count", "conditional_probability_with_replacements",
+            "mempool_depth", "blocks_found_count",
+            "block_discovery_timestamp", "snapshot_timestamp", "snapshot_age_ms", "reorged",
+            "blocks_since_snapshot", "mempool_subset_txns_in_blocks_since_snapshot_count", "cumulative_probability",
+            "template_total_fees", "template_total_weight", "template_percentage_of_total_possible_fees",
         ])?;
 
-        for record in intermediate_result {
+        for record in &result {
             wtr.write_record([
+                &record.subset_name,
                 &record.prev_block_height.to_string(),
                 &record.prev_block_hash,
                 &record.target_block_height.to_string(),
                 &record.target_block_hash,
-                &record.mempool_fee_rate_estimate.to_string(),
-                &record.mempool_subset_fee_rate_estimate.to_string(),
+                &record.mempool_fee_rate_estimate.in_units(self.units).to_string(),
+                &record.mempool_subset_fee_rate_estimate.in_units(self.units).to_string(),
                 &record.mempool_subset_txns_count.to_string(),
                 &record.target_block_txns_count.to_string(),
                 &record.mempool_subset_txns_in_target_block_count.to_string(),
                 &record.conditional_probability.to_string(),
+                &record.mempool_subset_txns_replaced_in_target_block_count.to_string(),
+                &record.conditional_probability_with_replacements.to_string(),
                 &record.mempool_depth.to_string(),
                 &record.blocks_found_count.to_string(),
                 &record.block_discovery_timestamp,
-                &record.snapshot_timestamp
+                &record.snapshot_timestamp,
+                &record.snapshot_age_ms.to_string(),
+                &record.reorged.to_string(),
+                &record.blocks_since_snapshot.to_string(),
+                &record.mempool_subset_txns_in_blocks_since_snapshot_count.to_string(),
+                &record.cumulative_probability.to_string(),
+                &record.template_total_fees.to_string(),
+                &record.template_total_weight.to_string(),
+                &record.template_percentage_of_total_possible_fees.to_string()
             ])?;
         }
 
@@ -120,6 +1163,19 @@ impl AnalyzerResultProcessor for AnalyzerResult {
 
         println!("Result written to CSV file successfully!");
 
+        for (column, order_by, file_name) in BREAKDOWNS {
+            self.save_breakdown(column, order_by, file_name)?;
+        }
+
+        self.save_fee_bands()?;
+        self.save_block_fidelity()?;
+        self.save_reference_fees()?;
+        self.save_template_diffs()?;
+
+        //reorged blocks stay in result.csv, flagged, but are left out of the summary like the breakdowns
+        let counted_result: Vec<AnalyzerResult> = result.into_iter().filter(|r| !r.reorged).collect();
+        self.save_summary(status, &counted_result)?;
+
         Ok(())
     }
-}
\ No newline at end of file
+}
@@ -1,32 +1,70 @@
 #![allow(unused)]
 use tokio::time::{self, Duration};
+use tokio::signal::unix::{signal, SignalKind};
 use tokio::sync::Mutex;
+use tokio::sync::mpsc::UnboundedReceiver;
+use tokio::task::JoinSet;
 use std::clone;
 use std::sync::{Arc, MutexGuard};
 use std::{error::Error, os::unix::process::parent_id, path::PathBuf, process::Command};
-use crate::block_data::{BlockTransaction, BlockMonitor};
-use crate::config::Config;
+use crate::block_data::{block_min_fee_rate, BlockBuilder, BlockTemplate, BlockTransaction, BlockMonitor};
+use crate::config::{Config, ResumeConfig, SubsetFilters};
+use crate::dashboard::Dashboard;
+use crate::fee_rate::FeeRate;
 use crate::mempool_data::{self, MempoolTransaction, MempoolData};
 use crate::mempool_data_subsets::{
     filter_mempool_txns, 
+    AgeFilter,
+    AncestorCountFilter,
+    CpfpChildFilter,
     HighFeeFilter, 
+    InputScriptTypeFilter,
     InputsCountFilter, 
+    InscriptionFilter,
     LowFeeFilter, 
     MempoolTransactionFilter, 
-    OutputsCountFilter
+    OpReturnFilter,
+    OutputScriptTypeFilter,
+    OutputsCountFilter,
+    RbfSignalingFilter,
+    UnconfirmedAncestorsFilter,
+    VsizeBucketFilter
 };
+use crate::txn_shape::TxnShapeCache;
 use crate::strategies::{FeeRateEstimator, select_strategy};
 use std::collections::HashMap;
 use std::collections::HashSet;
+use std::collections::VecDeque;
 use crate::config::Commands;
-use crate::result::{AnalyzerResult, AnalyzerResultProcessor, AnalyzerResultUpdate};
+use crate::chain_source::{BitcoindChainSource, BlockRef, ChainSource, ChainTracker, ScriptedChainSource};
+use crate::esplora::{EsploraClient, EsploraSource};
+use crate::block_fidelity::BlockFidelity;
+use crate::fee_comparison::FeeComparison;
+use crate::template_diff::TemplateDiff;
+use crate::mempool_state::{BitcoindMempoolSource, MempoolSource, MempoolState};
+use crate::zmq_events::{subscribe, ChainEvent};
+use crate::manifest::{create_run_dir, generate_run_id, RunManifest};
+use crate::result::{AnalyzerResult, AnalyzerResultProcessor, FeeBandResult, ResultStore, RunStatus, ScoredSubset};
 use chrono::{DateTime, Utc, TimeZone};
 
+//how many recent snapshots are kept to pick the one taken just before a block from
+const SNAPSHOT_RING_SIZE: usize = 5;
+
 #[derive(Debug)]
 pub enum AnalyzerError {
     SomethingWentWrong
 }
 
+impl std::fmt::Display for AnalyzerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AnalyzerError::SomethingWentWrong => write!(f, "Could not run bitcoin-cli"),
+        }
+    }
+}
+
+impl Error for AnalyzerError {}
+
 #[derive(Clone)]
 pub struct Runner<'a> {
     pub strategy: &'a dyn FeeRateEstimator,
@@ -44,6 +82,44 @@ fn get_timestamp() -> String {
     formatted_time
 }
 
+fn get_unix_time() -> u64 {
+    Utc::now().timestamp() as u64
+}
+
+fn get_unix_time_ms() -> u64 {
+    Utc::now().timestamp_millis() as u64
+}
+
+fn format_unix_time_ms(unix_time_ms: u64) -> String {
+    Utc.timestamp_millis_opt(unix_time_ms as i64)
+        .single()
+        .map(|time| time.format("%Y-%m-%d %H:%M:%S%z").to_string())
+        .unwrap_or_else(get_timestamp)
+}
+
+//maps subset txns missing from the block to the block txn spending one of the same outputs,
+//i.e. a replacement (or other conflict) of the original that was mined instead. needs decoded txns
+fn find_replacements(
+    filtered_txns: &[MempoolTransaction],
+    txns_in_block: &[BlockTransaction],
+) -> HashMap<String, String> {
+    let txids_in_block: HashSet<&String> = txns_in_block.iter().map(|tx| &tx.txid).collect();
+    let spenders_in_block: HashMap<String, &String> = txns_in_block
+        .iter()
+        .flat_map(|tx| tx.spent_outpoints().map(move |outpoint| (outpoint, &tx.txid)))
+        .collect();
+
+    filtered_txns
+        .iter()
+        .filter(|tx| !txids_in_block.contains(&tx.txid))
+        .filter_map(|tx| {
+            let shape = tx.shape.as_ref()?;
+            let replacement_txid = shape.spent_outpoints.iter().find_map(|outpoint| spenders_in_block.get(outpoint))?;
+            Some((tx.txid.clone(), replacement_txid.to_string()))
+        })
+        .collect()
+}
+
 fn find_common_transactions(
     filtered_txns: &[MempoolTransaction],
     txns_in_block: &[BlockTransaction],
@@ -56,162 +132,469 @@ fn find_common_transactions(
         .cloned()
         .collect()
 }
+//inclusion of the whole snapshot mempool split by effective fee rate, which traces the
+//empirical next-block inclusion curve the strategy estimate can be compared against
+fn score_fee_bands(
+    bands: &[(FeeRate, FeeRate)],
+    mempool_txns: &[MempoolTransaction],
+    txids_in_block: &HashSet<&String>,
+) -> Vec<FeeBandResult> {
+    bands
+        .iter()
+        .map(|(lower, upper)| {
+            let band_txns: Vec<&MempoolTransaction> = mempool_txns
+                .iter()
+                .filter(|tx| tx.effective_fee_rate() >= *lower && tx.effective_fee_rate() < *upper)
+                .collect();
+
+            FeeBandResult {
+                lower: *lower,
+                upper: *upper,
+                mempool_txns_count: band_txns.len(),
+                mempool_txns_in_target_block_count: band_txns.iter().filter(|tx| txids_in_block.contains(&tx.txid)).count(),
+            }
+        })
+        .collect()
+}
+
 pub struct MempoolFilterParams<'a> {
-    config: Config,
-    runner: Runner<'a>,
-    threshold: f64,
-    mempool_txns: Vec<MempoolTransaction>
+    filters: &'a SubsetFilters,
+    strategy_estimate: FeeRate,
+    mempool_txns: &'a [MempoolTransaction],
+    //built from mempool_txns
+    template: &'a BlockTemplate,
+    prev_block_min_fee_rate: Option<FeeRate>,
+    snapshot_time: u64
 }
 
-pub fn fetch_mempool_txns_subset<'a>(filter_params: MempoolFilterParams<'a>) -> Result<(Vec<MempoolTransaction>, f64), Box<dyn Error>> 
+pub fn fetch_mempool_txns_subset<'a>(filter_params: MempoolFilterParams<'a>) -> Result<Vec<MempoolTransaction>, Box<dyn Error + Send + Sync>> 
 {
-    let mut filtered_txns = vec![];
-    let mut subset_fee_rate_estimate= 0.0;
+    let SubsetFilters {
+        low_fee_txns,
+        high_fee_txns,
+        fee_threshold,
+        inputs,
+        outputs,
+        age,
+        vsize_bucket,
+        input_script_type,
+        output_script_type,
+        has_unconfirmed_ancestors,
+        cpfp_child,
+        ancestor_count,
+        rbf_signaling,
+        no_rbf,
+        op_return,
+        inscription,
+        filter,
+    } = filter_params.filters;
 
-    match &filter_params.config.commands {
-        Commands::SubsetSample {
-            low_fee_txns,
-            high_fee_txns ,
-            inputs,
-            outputs,
-        } => {
+    let mut filters: Vec<Box<dyn MempoolTransactionFilter>> = Vec::new();
 
-            let mut filters: Vec<Box<dyn MempoolTransactionFilter>> = Vec::new();
+    if *low_fee_txns || *high_fee_txns {
+        let threshold = fee_threshold.resolve(filter_params.strategy_estimate, filter_params.mempool_txns, filter_params.template, filter_params.prev_block_min_fee_rate);
 
-            if *low_fee_txns {
-                filters.push(Box::new(LowFeeFilter { threshold: filter_params.threshold }));
-            }
+        if *low_fee_txns {
+            filters.push(Box::new(LowFeeFilter { threshold }));
+        } else {
+            filters.push(Box::new(HighFeeFilter { threshold }));
+        }
+    }
 
-            if *high_fee_txns {
-                filters.push(Box::new(HighFeeFilter { threshold: filter_params.threshold }));
-            }
+    if let Some((comparison, count)) = inputs {
+        filters.push(Box::new(InputsCountFilter { comparison: comparison.clone(), count: *count }));
+    }
 
-            if let Some((comparison, count)) = inputs {
-                filters.push(Box::new(InputsCountFilter { comparison: comparison.clone(), count: *count }));
-            }
+    if let Some((comparison, count)) = outputs {
+        filters.push(Box::new(OutputsCountFilter { comparison: comparison.clone(), count: *count }));
+    }
 
-            if let Some((comparison, count)) = outputs {
-                filters.push(Box::new(OutputsCountFilter { comparison: comparison.clone(), count: *count }));
-            }
+    if let Some((comparison, seconds)) = age {
+        filters.push(Box::new(AgeFilter { comparison: comparison.clone(), seconds: *seconds as u64, snapshot_time: filter_params.snapshot_time }));
+    }
 
-            filtered_txns = filter_mempool_txns(&filter_params.mempool_txns, &filters);
-            subset_fee_rate_estimate = filter_params.runner.strategy.estimate_fee_rate(&filtered_txns);
-        }
+    if let Some(bucket) = vsize_bucket {
+        filters.push(Box::new(VsizeBucketFilter { bucket: bucket.clone() }));
+    }
+
+    if let Some(script_type) = input_script_type {
+        filters.push(Box::new(InputScriptTypeFilter { script_type: *script_type }));
+    }
+
+    if let Some(script_type) = output_script_type {
+        filters.push(Box::new(OutputScriptTypeFilter { script_type: *script_type }));
+    }
+
+    if *has_unconfirmed_ancestors {
+        filters.push(Box::new(UnconfirmedAncestorsFilter));
+    }
+
+    if *cpfp_child {
+        filters.push(Box::new(CpfpChildFilter));
     }
 
-    Ok((filtered_txns, subset_fee_rate_estimate))
+    if let Some((min, max)) = ancestor_count {
+        filters.push(Box::new(AncestorCountFilter { min: *min, max: *max }));
+    }
+
+    if *rbf_signaling || *no_rbf {
+        filters.push(Box::new(RbfSignalingFilter { signaling: *rbf_signaling }));
+    }
+
+    if *op_return {
+        filters.push(Box::new(OpReturnFilter));
+    }
+
+    if *inscription {
+        filters.push(Box::new(InscriptionFilter));
+    }
+
+    if let Some(expression) = filter {
+        filters.push(Box::new(expression.clone()));
+    }
+
+    Ok(filter_mempool_txns(filter_params.mempool_txns, &filters))
 }
 
 
-pub async fn run_analysis(
-    config: Config, 
-    last_snapshot: Arc<Mutex<SnapshotData>>,
-    mempool_txns: Vec<MempoolTransaction>
-) -> Result<AnalyzerResult, Box<dyn Error + Send + Sync>> {
+//how run_analysis learns that blocks were found
+pub enum BlockSighting {
+    //compare the chain tip with the last scored block. a new block counts as first seen when getblockcount reports it
+    Poll,
+    //zmq announced a block at this unix time in milliseconds
+    Announced(u64),
+    //blocks are scored when zmq announces them, so the ticks in between leave them alone
+    NotChecked,
+}
 
-    let last_snapshot = last_snapshot.lock().await;
-    
-    let mut result = vec![];
+//a block to score, looked up by hash so a block replaced in a reorg is not mistaken for its replacement
+struct FoundBlock {
+    block: BlockRef,
+    txns: Vec<BlockTransaction>,
+    first_seen_ms: u64,
+    //position among the blocks found since the snapshot, and the txids of those found before it
+    blocks_since_snapshot: usize,
+    earlier_block_txids: HashSet<String>
+}
 
-    let strategy = select_strategy(&config.strategy_name);
-    let runner = Runner::new(&*strategy);
-    
-    let mut target_block_txns: Vec<BlockTransaction> = vec![];
-    let mut filtered_txns_in_block: Vec<MempoolTransaction> = vec![];
+//scores every subset of the snapshot against the found block and stores the results
+fn score_block(
+    config: &Config,
+    result_store: &mut ResultStore,
+    runner: &Runner,
+    snapshot: &SnapshotData,
+    found_block: &FoundBlock,
+    blocks_found_count: usize
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    println!("Target Block Found!");
 
-    let prev_block_height = BlockMonitor::get_prev_block_height().expect("Error getting prev block height");
-    let target_block_height = prev_block_height + 1;
+    let subsets = config.subsets();
 
-    let mut blocks_found_count = last_snapshot.analyzer_result.blocks_found_count;
+    let target_block_hash = &found_block.block.hash;
+    let target_block_txns = &found_block.txns;
+    let block_first_seen_ms = found_block.first_seen_ms;
 
-   if check_if_target_block_found(last_snapshot.target_block_height, target_block_height) {
-        
-        println!("Target Block Found!");
+    let template = &snapshot.template;
 
-        let fee_rate_estimate = runner.strategy.estimate_fee_rate(&last_snapshot.mempool_txns);
+    let mut scored_subsets = vec![];
 
+    //every subset is scored against the same snapshot and block
+    for (subset, last_result) in subsets.iter().zip(&snapshot.analyzer_results) {
         let filter_params = MempoolFilterParams {
-            config: config.clone(),
-            runner: runner.clone(),
-            threshold: fee_rate_estimate,
-            mempool_txns: last_snapshot.mempool_txns.clone()
+            filters: &subset.filters,
+            strategy_estimate: last_result.mempool_fee_rate_estimate,
+            mempool_txns: &snapshot.mempool_txns,
+            template,
+            prev_block_min_fee_rate: snapshot.prev_block_min_fee_rate,
+            snapshot_time: snapshot.mempool_time()
         };
     
-        let (filtered_txns, subset_fee_rate_estimate) = fetch_mempool_txns_subset(filter_params).expect("Error fetching mempool txns subset");
+        let filtered_txns = fetch_mempool_txns_subset(filter_params)?;
 
-        let target_block_hash = BlockMonitor::get_block_hash(last_snapshot.target_block_height).expect("Error getting block hash");
-        let target_block_txns = BlockMonitor::get_target_block_txns(last_snapshot.target_block_height).expect("Error getting target block txns");
         let filtered_txns_in_block = find_common_transactions (
             &filtered_txns, 
-            &target_block_txns
+            target_block_txns
         );
 
-        blocks_found_count = blocks_found_count + 1;
+        let replacements = if config.track_replacements {
+            find_replacements(&filtered_txns, target_block_txns)
+        } else {
+            HashMap::new()
+        };
+
+        let filtered_txns_in_blocks_since_snapshot_count = filtered_txns_in_block.len() + filtered_txns
+            .iter()
+            .filter(|tx| found_block.earlier_block_txids.contains(&tx.txid))
+            .count();
 
-        let mut analyzer_result = AnalyzerResult {
-            prev_block_height: last_snapshot.analyzer_result.prev_block_height,
-            prev_block_hash: last_snapshot.analyzer_result.prev_block_hash.clone(),
-            target_block_height: last_snapshot.analyzer_result.target_block_height,
-            target_block_hash,
-            mempool_fee_rate_estimate: last_snapshot.analyzer_result.mempool_fee_rate_estimate,
-            mempool_subset_fee_rate_estimate: last_snapshot.analyzer_result.mempool_subset_fee_rate_estimate,
+        let analyzer_result = AnalyzerResult {
+            subset_name: subset.name.clone(),
+            prev_block_height: last_result.prev_block_height,
+            prev_block_hash: last_result.prev_block_hash.clone(),
+            target_block_height: found_block.block.height,
+            target_block_hash: target_block_hash.clone(),
+            mempool_fee_rate_estimate: last_result.mempool_fee_rate_estimate,
+            mempool_subset_fee_rate_estimate: last_result.mempool_subset_fee_rate_estimate,
             mempool_subset_txns_count: filtered_txns.len(),
             target_block_txns_count: target_block_txns.len(),
             mempool_subset_txns_in_target_block_count: filtered_txns_in_block.len(),
             conditional_probability: filtered_txns_in_block.len() as f64 / filtered_txns.len() as f64,
-            mempool_depth: last_snapshot.mempool_txns.len(),
+            mempool_subset_txns_replaced_in_target_block_count: replacements.len(),
+            conditional_probability_with_replacements: (filtered_txns_in_block.len() + replacements.len()) as f64 / filtered_txns.len() as f64,
+            mempool_depth: snapshot.mempool_txns.len(),
             blocks_found_count,
-            block_discovery_timestamp: get_timestamp(),
-            snapshot_timestamp: last_snapshot.analyzer_result.snapshot_timestamp.clone()
+            block_discovery_timestamp: format_unix_time_ms(block_first_seen_ms),
+            snapshot_timestamp: last_result.snapshot_timestamp.clone(),
+            snapshot_age_ms: block_first_seen_ms.saturating_sub(snapshot.mempool_time_ms),
+            reorged: false,
+            blocks_since_snapshot: found_block.blocks_since_snapshot,
+            mempool_subset_txns_in_blocks_since_snapshot_count: filtered_txns_in_blocks_since_snapshot_count,
+            cumulative_probability: filtered_txns_in_blocks_since_snapshot_count as f64 / filtered_txns.len() as f64,
+            template_total_fees: template.metrics.total_fees,
+            template_total_weight: template.metrics.total_weight,
+            template_percentage_of_total_possible_fees: template.metrics.percentage_of_total_possible_fees
         };
 
-        if analyzer_result.result_exists() {
-            result = analyzer_result.load_intermediate_result().expect("Error loading intermediate result");
-        }
+        scored_subsets.push(ScoredSubset { result: analyzer_result, subset_txns: filtered_txns, replacements });
+    }
 
-        let item_already_exists = result.iter().any(|r| r.prev_block_height == last_snapshot.analyzer_result.prev_block_height);
-    
-        if !item_already_exists {
-            result.push(analyzer_result.clone());
-            analyzer_result.save_intermediate_result(result);
-        }
+    let txids_in_block: HashSet<&String> = target_block_txns.iter().map(|tx| &tx.txid).collect();
+
+    let fee_bands = score_fee_bands(&config.fee_bands(), &snapshot.mempool_txns, &txids_in_block);
+
+    let fidelity = BlockFidelity::measure(template, &snapshot.mempool_txns, target_block_txns);
+
+    result_store.save_intermediate_result(&scored_subsets, &fee_bands, &fidelity, &txids_in_block, snapshot.mempool_time())?;
+
+    Ok(())
+}
+
+//a mempool fetched while target_block_height was next
+struct SnapshotParams<'a> {
+    mempool_txns: &'a [MempoolTransaction],
+    target_block_height: u32,
+    mempool_time_ms: u64,
+    blocks_found_count: usize,
+    //of the block before target_block_height, if known
+    prev_block_min_fee_rate: Option<FeeRate>
+}
+
+//the strategy's and each subset's estimate for a snapshot's mempool, along with the template built from it
+fn estimate_snapshot(
+    config: &Config,
+    chain_source: &dyn ChainSource,
+    runner: &Runner,
+    snapshot_params: SnapshotParams
+) -> Result<(BlockTemplate, Vec<AnalyzerResult>), Box<dyn Error + Send + Sync>> {
+    let SnapshotParams { mempool_txns, target_block_height, mempool_time_ms, blocks_found_count, prev_block_min_fee_rate } = snapshot_params;
+
+    let template = BlockBuilder::build_block(mempool_txns).map_err(|e| e.to_string())?;
+    let fee_rate_estimate = runner.strategy.estimate_fee_rate(mempool_txns, &template);
+
+    let prev_block_height = target_block_height - 1;
+    let prev_block_hash = chain_source.block_hash(prev_block_height)?;
+    let snapshot_timestamp = format_unix_time_ms(mempool_time_ms);
+
+    let mut analyzer_results = vec![];
+
+    for subset in config.subsets() {
+        let filter_params = MempoolFilterParams {
+            filters: &subset.filters,
+            strategy_estimate: fee_rate_estimate,
+            mempool_txns,
+            template: &template,
+            prev_block_min_fee_rate,
+            snapshot_time: mempool_time_ms / 1000
+        };
+        
+        let filtered_txns = fetch_mempool_txns_subset(filter_params)?;
+        let subset_template = BlockBuilder::build_block(&filtered_txns).map_err(|e| e.to_string())?;
+        let subset_fee_rate_estimate = runner.strategy.estimate_fee_rate(&filtered_txns, &subset_template);
+
+        analyzer_results.push(AnalyzerResult {
+            subset_name: subset.name,
+            prev_block_height,
+            prev_block_hash: prev_block_hash.clone(),
+            target_block_height,
+            target_block_hash: "".to_string(),
+            mempool_fee_rate_estimate: fee_rate_estimate,
+            mempool_subset_fee_rate_estimate: subset_fee_rate_estimate,
+            mempool_subset_txns_count: filtered_txns.len(),
+            mempool_depth: mempool_txns.len(),
+            blocks_found_count,
+            snapshot_timestamp: snapshot_timestamp.clone(),
+            ..AnalyzerResult::default()
+        });
     }
 
-    let fee_rate_estimate = runner.clone().strategy.estimate_fee_rate(&mempool_txns);
+    Ok((template, analyzer_results))
+}
+
+//compares the snapshot's template with whichever references are enabled. a failed fetch only leaves
+//the comparison without that reference
+fn compare_templates(config: &Config, template: &BlockTemplate) -> TemplateComparisons {
+
+    let fee_comparison = config.mempool_space.as_ref().and_then(|base_url| {
+        FeeComparison::fetch(&EsploraClient::new(base_url), &template.txns)
+            .map_err(|e| eprintln!("Could not fetch mempool.space fees: {}", e))
+            .ok()
+    });
 
-    let filter_params = MempoolFilterParams {
-        config,
-        runner,
-        threshold: fee_rate_estimate,
-        mempool_txns: mempool_txns.clone()
+    let template_diff = if config.compare_block_template {
+        TemplateDiff::fetch(&template.txns)
+            .map_err(|e| eprintln!("Could not diff against getblocktemplate: {}", e))
+            .ok()
+    } else {
+        None
     };
-    
-    let (filtered_txns, subset_fee_rate_estimate) = fetch_mempool_txns_subset(filter_params)
-    .expect("Error fetching mempool txns subset");
-
-    let prev_block_height = BlockMonitor::get_prev_block_height().expect("Error getting prev block height");
-    let prev_block_hash = BlockMonitor::get_block_hash(prev_block_height).expect("Error getting block hash");
-    let target_block_height = prev_block_height + 1;
-    let mut target_block_hash = "".to_string();
-
-    let mut analyzer_result = AnalyzerResult {
-        prev_block_height,
-        prev_block_hash,
-        target_block_height,
-        target_block_hash,
-        mempool_fee_rate_estimate: fee_rate_estimate,
-        mempool_subset_fee_rate_estimate: subset_fee_rate_estimate,
-        mempool_subset_txns_count: filtered_txns.len(),
-        target_block_txns_count: target_block_txns.len(),
-        mempool_subset_txns_in_target_block_count: filtered_txns_in_block.len(),
-        conditional_probability: filtered_txns_in_block.len() as f64 / filtered_txns.len() as f64,
-        mempool_depth: last_snapshot.mempool_txns.len(),
-        blocks_found_count,
-        block_discovery_timestamp: "".to_string(),
-        snapshot_timestamp: get_timestamp()
+
+    TemplateComparisons { fee_comparison, template_diff }
+}
+
+//compares the snapshot's template with the references and saves whatever was fetched. the fetches
+//block, so they run on the blocking pool and never under the snapshots lock
+async fn compare_snapshot(
+    config: Config,
+    run_dir: PathBuf,
+    run_id: String,
+    snapshot: Arc<SnapshotData>
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    tokio::task::spawn_blocking(move || {
+        let comparisons = compare_templates(&config, &snapshot.template);
+
+        if comparisons.fee_comparison.is_none() && comparisons.template_diff.is_none() {
+            return Ok(());
+        }
+
+        ResultStore::open(&run_dir, &run_id)?.save_comparisons(snapshot.analyzer_result(), &comparisons)
+    }).await?
+}
+
+//a failed comparison is only logged, as the references are a diagnostic
+fn spawn_comparison(comparison_tasks: &mut JoinSet<()>, config: Config, run_dir: PathBuf, run_id: String, snapshot: Arc<SnapshotData>) {
+    let comparison = compare_snapshot(config, run_dir, run_id, snapshot);
+
+    comparison_tasks.spawn(async move {
+        if let Err(e) = comparison.await {
+            eprintln!("Could not save template comparison: {}", e);
+        }
+    });
+}
+
+//the mempool as of one tick, to be added to the snapshot ring
+pub struct FetchedMempool {
+    mempool_txns: Vec<MempoolTransaction>,
+    mempool_time_ms: u64,
+    //None when the tip moved while the mempool was fetched, since it is then unclear which block it was waiting on
+    target_block_height: Option<u32>
+}
+
+//scores any newly found block against the latest snapshot taken before it was first seen, then adds
+//the fetched mempool to the ring, returning the snapshot if one was taken
+pub async fn run_analysis(
+    config: Config, 
+    run_dir: PathBuf,
+    run_id: String,
+    snapshots: Arc<Mutex<SnapshotRing>>,
+    chain_source: Arc<dyn ChainSource>,
+    fetched_mempool: FetchedMempool,
+    block_sighting: BlockSighting
+) -> Result<Option<Arc<SnapshotData>>, Box<dyn Error + Send + Sync>> {
+    let FetchedMempool { mempool_txns, mempool_time_ms, target_block_height: snapshot_target_height } = fetched_mempool;
+
+    //held throughout so concurrent analyses cannot score the same block twice
+    let mut snapshots = snapshots.lock().await;
+
+    let strategy = select_strategy(&config.strategy_name);
+    let runner = Runner::new(&*strategy);
+
+    let block_first_seen_ms = match block_sighting {
+        BlockSighting::Poll => Some(get_unix_time_ms()),
+        BlockSighting::Announced(block_first_seen_ms) => Some(block_first_seen_ms),
+        BlockSighting::NotChecked => None,
     };
 
-    Ok(analyzer_result)
+    if let Some(block_first_seen_ms) = block_first_seen_ms {
+        let chain_update = snapshots.chain.update(&*chain_source)?;
+
+        //blocks already scored that are no longer in the best chain stop counting toward the run
+        if let Some(fork_block) = chain_update.disconnected.first() {
+            let block_hashes: Vec<String> = chain_update.disconnected.iter().map(|block| block.hash.clone()).collect();
+            let reorged_count = ResultStore::open(&run_dir, &run_id)?.mark_reorged(&block_hashes)?;
+            snapshots.blocks_found_count = snapshots.blocks_found_count.saturating_sub(reorged_count);
+
+            println!(
+                "Chain Reorganization! {} block(s) from height {} no longer in the best chain, {} scored block(s) flagged as reorged",
+                chain_update.disconnected.len(),
+                fork_block.height,
+                reorged_count
+            );
+        }
+
+        //every block since the last look is scored, a block replacing one lost in a reorg like any other. a block
+        //with no snapshot of its own, e.g. the second of two found within a tick, is scored against the snapshot of
+        //the block before it, with the subset's inclusion accumulated over both
+        let mut last_snapshot: Option<Arc<SnapshotData>> = None;
+        let mut earlier_block_txids: HashSet<String> = HashSet::new();
+        let mut blocks_since_snapshot = 0;
+
+        for block in &chain_update.connected {
+            if let Some(snapshot) = snapshots.before_block(block.height, block_first_seen_ms) {
+                last_snapshot = Some(snapshot);
+                earlier_block_txids.clear();
+                blocks_since_snapshot = 0;
+            }
+
+            //worked out for every block, as the next snapshot's prev-block-min threshold
+            let block_txns = chain_source.block_txns(&block.hash)?;
+            let snapshot_before_block = last_snapshot.clone().unwrap_or_else(|| snapshots.latest());
+            snapshots.min_fee_rate = block_min_fee_rate(&block_txns, &snapshot_before_block.mempool_txns).map(|fee_rate| (block.height, fee_rate));
+
+            let Some(snapshot) = &last_snapshot else {
+                eprintln!("No snapshot was taken before block {} was first seen. Skipping it", block.height);
+                continue;
+            };
+
+            let found_block = FoundBlock {
+                txns: block_txns,
+                block: block.clone(),
+                first_seen_ms: block_first_seen_ms,
+                blocks_since_snapshot: blocks_since_snapshot + 1,
+                earlier_block_txids: earlier_block_txids.clone()
+            };
+            let blocks_found_count = snapshots.blocks_found_count + 1;
+
+            let mut result_store = ResultStore::open(&run_dir, &run_id)?;
+            score_block(&config, &mut result_store, &runner, snapshot, &found_block, blocks_found_count)?;
+            snapshots.blocks_found_count = blocks_found_count;
+
+            earlier_block_txids.extend(found_block.txns.into_iter().map(|tx| tx.txid));
+            blocks_since_snapshot = found_block.blocks_since_snapshot;
+        }
+    }
+
+    if let Some(target_block_height) = snapshot_target_height {
+        let prev_block_min_fee_rate = snapshots.min_fee_rate_of(target_block_height - 1);
+        let (template, analyzer_results) = estimate_snapshot(&config, &*chain_source, &runner, SnapshotParams {
+            mempool_txns: &mempool_txns,
+            target_block_height,
+            mempool_time_ms,
+            blocks_found_count: snapshots.blocks_found_count,
+            prev_block_min_fee_rate
+        })?;
+
+        return Ok(Some(snapshots.push(SnapshotData {
+            target_block_height,
+            mempool_txids: mempool_txns.iter().map(|txn| txn.txid.clone()).collect(),
+            mempool_txns,
+            mempool_time_ms,
+            template,
+            prev_block_min_fee_rate,
+            analyzer_results
+        })));
+    }
+
+    Ok(None)
 }
 
 fn check_if_target_block_found(last_target_block_height: u32, target_block_height: u32) -> bool {
@@ -228,104 +611,505 @@ pub fn bcli(cmd: &str) -> Result<Vec<u8>, AnalyzerError> {
         .map_err(|_| AnalyzerError::SomethingWentWrong)?;
 
     if result.status.success() {
-        return Ok(result.stdout);
+        Ok(result.stdout)
     } else {
-        return Ok(result.stderr);
+        Ok(result.stderr)
     }
 }
 
 #[derive(Debug)]
 pub struct SnapshotData {
-    target_block_height: u32,
-    mempool_txids: HashSet<String>,
-    mempool_txns: Vec<MempoolTransaction>,
-    analyzer_result: AnalyzerResult
+    pub(crate) target_block_height: u32,
+    pub(crate) mempool_txids: HashSet<String>,
+    pub(crate) mempool_txns: Vec<MempoolTransaction>,
+    //unix time in milliseconds at which mempool_txns was fetched
+    pub(crate) mempool_time_ms: u64,
+    //built from mempool_txns once and reused for the estimates, comparisons, scoring and dashboard
+    pub(crate) template: BlockTemplate,
+    pub(crate) prev_block_min_fee_rate: Option<FeeRate>,
+    //one per subset, in the order of Config::subsets
+    pub(crate) analyzer_results: Vec<AnalyzerResult>
 }
 
-async fn fetch_current_mempool_txns() -> Result<(Vec<MempoolTransaction>, HashSet<String>), Box<dyn Error>> {
-    let mut mempool_txns: Vec<MempoolTransaction> = vec![];
+//reference templates compared against a snapshot's BlockBuilder template, each only fetched when its
+//option is set
+#[derive(Debug, Default)]
+pub struct TemplateComparisons {
+    //--mempool-space
+    pub(crate) fee_comparison: Option<FeeComparison>,
+    //--compare-block-template
+    pub(crate) template_diff: Option<TemplateDiff>
+}
 
-    let raw_mempool_data: Vec<u8> = bcli(&format!("getrawmempool true")).expect("Error getting raw mempool");
-    let mempool_data_str = String::from_utf8(raw_mempool_data).expect("Failed to convert bytes to string");
-    let mempool_data: HashMap<String, MempoolData> = serde_json::from_str(&mempool_data_str).expect("Could not deserialize mempool data");
-        
-    mempool_txns = MempoolTransaction::fetch_mempool_txns(&mempool_data).expect("Could not fetch mempool txns");
-    let mut mempool_txids: HashSet<String> = HashSet::new();
+impl SnapshotData {
+    //the default subset's result, which also carries the run-wide fields such as blocks_found_count
+    pub(crate) fn analyzer_result(&self) -> &AnalyzerResult {
+        &self.analyzer_results[0]
+    }
+
+    pub(crate) fn mempool_time(&self) -> u64 {
+        self.mempool_time_ms / 1000
+    }
+}
+
+//the most recent snapshots, oldest first, along with the chain as of the last block scored against
+//them. each holds a copy of the mempool, so only a few seconds' worth are kept
+#[derive(Debug)]
+pub struct SnapshotRing {
+    snapshots: VecDeque<Arc<SnapshotData>>,
+    pub(crate) chain: ChainTracker,
+    pub(crate) blocks_found_count: usize,
+    //lowest fee rate mined in the last block connected, by height
+    pub(crate) min_fee_rate: Option<(u32, FeeRate)>
+}
+
+impl SnapshotRing {
+    pub(crate) fn new(first_snapshot: SnapshotData, chain: ChainTracker, blocks_found_count: usize) -> Self {
+        let min_fee_rate = first_snapshot.prev_block_min_fee_rate.map(|fee_rate| (first_snapshot.target_block_height - 1, fee_rate));
+
+        SnapshotRing {
+            snapshots: VecDeque::from([Arc::new(first_snapshot)]),
+            chain,
+            blocks_found_count,
+            min_fee_rate
+        }
+    }
+
+    pub(crate) fn min_fee_rate_of(&self, block_height: u32) -> Option<FeeRate> {
+        self.min_fee_rate.filter(|(height, _)| *height == block_height).map(|(_, fee_rate)| fee_rate)
+    }
+
+    //analyses can finish out of order, so snapshots are inserted by the time they were taken
+    pub(crate) fn push(&mut self, snapshot: SnapshotData) -> Arc<SnapshotData> {
+        let snapshot = Arc::new(snapshot);
+        let position = self.snapshots.iter().rposition(|s| s.mempool_time_ms <= snapshot.mempool_time_ms).map_or(0, |i| i + 1);
+        self.snapshots.insert(position, snapshot.clone());
+
+        if self.snapshots.len() > SNAPSHOT_RING_SIZE {
+            self.snapshots.pop_front();
+        }
+
+        snapshot
+    }
+
+    pub(crate) fn latest(&self) -> Arc<SnapshotData> {
+        self.snapshots.back().expect("Snapshot ring is never empty").clone()
+    }
 
-    for txn in mempool_txns.clone() {
-        mempool_txids.insert(txn.txid);
+    //the latest snapshot taken while block_height was the next block and strictly before it was first seen
+    pub(crate) fn before_block(&self, block_height: u32, block_first_seen_ms: u64) -> Option<Arc<SnapshotData>> {
+        self.snapshots
+            .iter()
+            .rev()
+            .find(|s| s.target_block_height == block_height && s.mempool_time_ms < block_first_seen_ms)
+            .cloned()
     }
+}
+
+//waits forever when not subscribed, so the select in run_loop only wakes on ticks
+async fn next_chain_event(chain_events: &mut Option<UnboundedReceiver<ChainEvent>>) -> Option<ChainEvent> {
+    match chain_events {
+        Some(chain_events) => chain_events.recv().await,
+        None => std::future::pending().await,
+    }
+}
 
-    Ok((mempool_txns, mempool_txids))
+//applies a transaction event to the mempool. rawtx is only used when the node does not publish
+//sequence, since it also reports every mined transaction and would cost a lookup for each
+async fn apply_txn_event(
+    mempool_state: &Arc<Mutex<MempoolState>>,
+    mempool_source: &dyn MempoolSource,
+    event: ChainEvent,
+    sequence_seen: bool
+) {
+    let mut mempool_state = mempool_state.lock().await;
+
+    match event {
+        ChainEvent::TxAdded(txid) => { mempool_state.add(mempool_source, &txid); }
+        ChainEvent::TxRemoved(txid) => { mempool_state.remove(&txid); }
+        ChainEvent::TxSeen(txid) if !sequence_seen => { mempool_state.add(mempool_source, &txid); }
+        _ => {}
+    }
+}
+
+type DataSources = (Arc<dyn ChainSource>, Arc<dyn MempoolSource>);
+
+//where blocks and the mempool are read from: a chain script replaces only the blocks, esplora both
+fn select_sources(config: &Config) -> Result<DataSources, Box<dyn Error>> {
+    let (chain_source, mempool_source): DataSources = match &config.esplora {
+        Some(base_url) => {
+            let esplora_source = Arc::new(EsploraSource::new(base_url));
+            (esplora_source.clone(), esplora_source)
+        }
+        None => (Arc::new(BitcoindChainSource), Arc::new(BitcoindMempoolSource)),
+    };
+
+    match &config.chain_script {
+        Some(chain_script) => Ok((Arc::new(ScriptedChainSource::load(chain_script)?), mempool_source)),
+        None => Ok((chain_source, mempool_source)),
+    }
 }
 
 pub async fn run_tasks(config: Config) -> Result<(), Box<dyn Error>> {
+    let strategy = select_strategy(&config.strategy_name);
+    let run_id = generate_run_id(&config.strategy_name);
+    let (chain_source, _) = select_sources(&config)?;
+    let start_height = chain_source.tip_height().expect("Could not get start height");
+    let started_at = get_timestamp();
+    let run_dir = create_run_dir(&config.output_dir, &run_id)?;
+
+    let manifest = RunManifest::new(&config, &run_id, strategy.name(), start_height, &started_at);
+    manifest.save(&run_dir)?;
+
+    let result_store = ResultStore::create_run(&run_dir, &run_id, &config, &started_at).expect("Could not create result store run");
+    println!("Saving results of run {} in {}", run_id, run_dir.display());
+
+    run_loop(config, run_dir, run_id, result_store, 0).await
+}
+
+//continues an interrupted run from its folder. blocks mined while the analyzer was down
+//have no snapshot to score against, so counting picks up again from the next block
+pub async fn resume_tasks(resume_config: ResumeConfig) -> Result<(), Box<dyn Error>> {
+    let run_dir = resume_config.run_dir;
+    let manifest = RunManifest::load(&run_dir)?;
+
+    let mut config = manifest.config;
+    config.tui = resume_config.tui;
+
+    let result_store = ResultStore::open(&run_dir, &manifest.run_id).expect("Could not open result store");
+    let blocks_found_count = result_store.load_intermediate_result()
+        .expect("Could not load intermediate result")
+        .iter()
+        .rfind(|r| !r.reorged)
+        .map(|r| r.blocks_found_count)
+        .unwrap_or(0);
+
+    println!(
+        "Resuming run {} in {} with {} of {} blocks found",
+        manifest.run_id,
+        run_dir.display(),
+        blocks_found_count,
+        config.duration
+    );
+
+    run_loop(config, run_dir, manifest.run_id, result_store, blocks_found_count).await
+}
+
+async fn run_loop(
+    config: Config,
+    run_dir: PathBuf,
+    run_id: String,
+    result_store: ResultStore,
+    blocks_found_count: usize
+) -> Result<(), Box<dyn Error>> {
     let interval = Duration::from_secs(1);
     let mut ticker = time::interval(interval);
 
-    let (mempool_txns, mempool_txids) = fetch_current_mempool_txns().await.expect("Could not fetch current mempool txns");
+    let shape_cache = if config.needs_txn_shapes() { Some(TxnShapeCache::default()) } else { None };
+
+    //subscribed before the mempool is first fetched so no event in between is lost
+    let mut chain_events = if config.zmq_endpoints.is_empty() {
+        None
+    } else {
+        Some(subscribe(&config.zmq_endpoints).await?)
+    };
+
+    let (chain_source, mempool_source) = select_sources(&config)?;
+
+    //fetched whole once, then kept up to date from zmq events or, when polling, with a diff each tick
+    let mempool_state = Arc::new(Mutex::new(MempoolState::fetch(&*mempool_source).expect("Could not fetch current mempool txns")));
+    let (mempool_txns, mempool_txids) = mempool_state.lock().await.snapshot(shape_cache.as_ref());
+    let mut mempool_time_ms = get_unix_time_ms();
+
+    let strategy = select_strategy(&config.strategy_name);
+    let runner = Runner::new(&*strategy);
+    let chain = ChainTracker::new(&*chain_source).expect("Could not get chain tip");
+
+    //the tip as the mempool state has seen it, which lags the node's until zmq announces a block
+    let mut tip_height = chain.tip_height();
+    let mut sequence_seen = false;
+    let mut last_block_hash = String::new();
 
-    let last_snapshot = Arc::new(Mutex::new(SnapshotData {
-        target_block_height: BlockMonitor::get_latest_target_block().expect("Could not get latest target block"),
-        mempool_txids: mempool_txids.clone(),
-        mempool_txns: mempool_txns.clone(),
-        analyzer_result: AnalyzerResult::default()
-    }));
+    //no snapshot saw the tip mined, so its txns are only fetched if a threshold needs them. fees the
+    //source does not give are unknown, as the tip's txns have left the mempool
+    let prev_block_min_fee_rate = if config.uses_prev_block_min_fee_rate() {
+        let tip_txns = chain_source.block_txns(&chain_source.block_hash(tip_height).map_err(|e| e.to_string())?).map_err(|e| e.to_string())?;
+        block_min_fee_rate(&tip_txns, &mempool_txns)
+    } else {
+        None
+    };
+
+    let (template, analyzer_results) = estimate_snapshot(&config, &*chain_source, &runner, SnapshotParams {
+        mempool_txns: &mempool_txns,
+        target_block_height: tip_height + 1,
+        mempool_time_ms,
+        blocks_found_count,
+        prev_block_min_fee_rate
+    }).map_err(|e| e.to_string())?;
+
+    let snapshots = Arc::new(Mutex::new(SnapshotRing::new(
+        SnapshotData {
+            target_block_height: tip_height + 1,
+            mempool_txids,
+            analyzer_results,
+            mempool_txns,
+            mempool_time_ms,
+            template,
+            prev_block_min_fee_rate,
+        },
+        chain,
+        blocks_found_count
+    )));
+
+    let mut dashboard = if config.tui { Some(Dashboard::new(&config, &run_dir, &run_id)?) } else { None };
+
+    //signal streams are created once so an interrupt arriving mid-tick is not missed
+    let mut sigint = signal(SignalKind::interrupt())?;
+    let mut sigterm = signal(SignalKind::terminate())?;
+    let mut analysis_tasks = JoinSet::new();
+    //reference templates are otherwise fetched at most this often rather than every tick, as each is a round
+    //trip to mempool.space or bitcoind
+    let mut comparison_ticker = time::interval(Duration::from_secs(config.compare_interval.max(1)));
+    let mut comparison_tasks = JoinSet::new();
+    let mut run_status = RunStatus::Complete;
+
+    if config.compares_every_snapshot() {
+        spawn_comparison(&mut comparison_tasks, config.clone(), run_dir.clone(), run_id.clone(), snapshots.lock().await.latest());
+    }
 
     loop {
-        ticker.tick().await;
+        let block_sighting = tokio::select! {
+            _ = ticker.tick() => {
+                if chain_events.is_none() { BlockSighting::Poll } else { BlockSighting::NotChecked }
+            }
+            event = next_chain_event(&mut chain_events) => {
+                match event {
+                    //scored straight away rather than on the next tick, against the mempool as of the
+                    //last event before the block. hashblock and sequence both announce it
+                    Some(ChainEvent::BlockConnected(block_hash)) if block_hash != last_block_hash => {
+                        let block_first_seen_ms = get_unix_time_ms();
+                        while analysis_tasks.join_next().await.is_some() {}
 
-        let config_clone = config.clone();
-        let last_snapshot_clone: Arc<Mutex<SnapshotData>> = last_snapshot.clone();
+                        //an announced block the chain source cannot return is logged and skipped
+                        let block_height = match chain_source.block_header(&block_hash) {
+                            Ok(header) => header.height,
+                            Err(e) => {
+                                eprintln!("Could not get header of announced block {}: {}", block_hash, e);
+                                continue;
+                            }
+                        };
+                        let block_txids: Vec<String> = match chain_source.block_txns(&block_hash) {
+                            Ok(block_txns) => block_txns.into_iter().map(|txn| txn.txid).collect(),
+                            Err(e) => {
+                                eprintln!("Could not get txns of announced block {}: {}", block_hash, e);
+                                continue;
+                            }
+                        };
+
+                        let (mempool_txns, mempool_txids) = mempool_state.lock().await.snapshot(shape_cache.as_ref());
+
+                        let mut snapshots = snapshots.lock().await;
+                        //without a snapshot the block is scored against the last one taken before it
+                        let prev_block_min_fee_rate = snapshots.min_fee_rate_of(block_height - 1);
+                        let snapshot_params = SnapshotParams {
+                            mempool_txns: &mempool_txns,
+                            target_block_height: block_height,
+                            mempool_time_ms,
+                            blocks_found_count: snapshots.blocks_found_count,
+                            prev_block_min_fee_rate
+                        };
+                        match estimate_snapshot(&config, &*chain_source, &runner, snapshot_params) {
+                            Ok((template, analyzer_results)) => {
+                                let snapshot = snapshots.push(SnapshotData {
+                                    target_block_height: block_height,
+                                    mempool_txids,
+                                    mempool_txns,
+                                    mempool_time_ms,
+                                    template,
+                                    prev_block_min_fee_rate,
+                                    analyzer_results
+                                });
+
+                                if config.compares_every_snapshot() {
+                                    spawn_comparison(&mut comparison_tasks, config.clone(), run_dir.clone(), run_id.clone(), snapshot);
+                                }
+                            }
+                            Err(e) => eprintln!("Could not estimate snapshot before block {}: {}", block_hash, e),
+                        }
+                        drop(snapshots);
 
-        let (mempool_txns, mempool_txids) = fetch_current_mempool_txns()
-        .await
-        .expect("Could not fetch current mempool txns");
+                        mempool_state.lock().await.remove_block_txns(&block_txids);
+                        mempool_time_ms = block_first_seen_ms;
+                        tip_height = block_height;
+                        last_block_hash = block_hash;
+
+                        BlockSighting::Announced(block_first_seen_ms)
+                    }
+                    Some(ChainEvent::BlockConnected(_)) => continue,
+                    //the block's transactions are announced again as they return to the mempool, and the
+                    //analysis flags any result scored against it
+                    Some(ChainEvent::BlockDisconnected(block_hash)) => {
+                        tip_height = match chain_source.block_header(&block_hash) {
+                            Ok(header) => header.height - 1,
+                            Err(e) => {
+                                eprintln!("Could not get header of disconnected block {}: {}", block_hash, e);
+                                continue;
+                            }
+                        };
+                        last_block_hash = String::new();
+
+                        BlockSighting::Announced(get_unix_time_ms())
+                    }
+                    Some(event) => {
+                        sequence_seen |= event.is_from_sequence();
+                        apply_txn_event(&mempool_state, &*mempool_source, event, sequence_seen).await;
+                        mempool_time_ms = get_unix_time_ms();
+                        continue;
+                    }
+                    None => {
+                        eprintln!("ZMQ events stopped. Falling back to polling the mempool");
+                        chain_events = None;
+                        continue;
+                    }
+                }
+            }
+            //the latest snapshot is compared unless the last comparison is still in flight
+            _ = comparison_ticker.tick(), if config.compares_templates() && !config.compares_every_snapshot() => {
+                while comparison_tasks.try_join_next().is_some() {}
 
-        tokio::spawn(async move {
+                if comparison_tasks.is_empty() {
+                    spawn_comparison(&mut comparison_tasks, config.clone(), run_dir.clone(), run_id.clone(), snapshots.lock().await.latest());
+                }
 
-            match run_analysis(config_clone, last_snapshot_clone.clone(), mempool_txns).await {
-                Ok(analyzer_result) => {
+                continue;
+            }
+            _ = sigint.recv() => {
+                run_status = RunStatus::Partial;
+                break;
+            }
+            _ = sigterm.recv() => {
+                run_status = RunStatus::Partial;
+                break;
+            }
+        };
 
-                    let mut snapshot = last_snapshot_clone.lock().await;
-                    
-                    let target_block_height = BlockMonitor::get_latest_target_block()
-                    .unwrap_or(snapshot.target_block_height);
+        //reap finished analysis and comparison tasks so the sets only hold in-flight ones
+        while analysis_tasks.try_join_next().is_some() {}
+        while comparison_tasks.try_join_next().is_some() {}
 
-                    let (mempool_txns, mempool_txids) = fetch_current_mempool_txns()
-                        .await
-                        .unwrap_or((snapshot.mempool_txns.clone(), snapshot.mempool_txids.clone()));
+        let config_clone = config.clone();
+        let run_dir_clone = run_dir.clone();
+        let run_id_clone = run_id.clone();
+        let snapshots_clone = snapshots.clone();
+        let chain_source_clone = chain_source.clone();
 
-                    snapshot.target_block_height = target_block_height;
-                    snapshot.mempool_txids = mempool_txids.clone();
-                    snapshot.mempool_txns = mempool_txns.clone();
-                    snapshot.analyzer_result = analyzer_result;
+        //when polling, the mempool is only labelled with a target height if the tip did not move while it
+        //was fetched. with zmq the state already reflects every block announced so far
+        let (mempool_delta, snapshot_target_height) = if chain_events.is_none() {
+            let tip_height_before = match chain_source.tip_height() {
+                Ok(tip_height) => tip_height,
+                Err(e) => {
+                    eprintln!("Could not get tip height: {}", e);
+                    continue;
                 }
+            };
+
+            let mut mempool_state = mempool_state.lock().await;
+            //a source that cannot be reached, e.g. a remote esplora, is tried again on the next tick
+            if let Err(e) = mempool_state.refresh(&*mempool_source) {
+                eprintln!("Could not refresh mempool: {}", e);
+                continue;
+            }
+            mempool_time_ms = get_unix_time_ms();
+
+            //the refreshed mempool's delta is kept for the next tick if the tip cannot be checked
+            let tip_height_after = match chain_source.tip_height() {
+                Ok(tip_height) => tip_height,
                 Err(e) => {
-                    eprintln!("Error in run_analysis: {}", e);
+                    eprintln!("Could not get tip height: {}", e);
+                    continue;
                 }
+            };
+            tip_height = tip_height_after;
+
+            (mempool_state.take_delta(), (tip_height_before == tip_height_after).then_some(tip_height_after + 1))
+        } else {
+            let mut mempool_state = mempool_state.lock().await;
+            mempool_state.refresh_package_stats(&*mempool_source);
+
+            (mempool_state.take_delta(), Some(tip_height + 1))
+        };
+
+        let (mempool_txns, _) = mempool_state.lock().await.snapshot(shape_cache.as_ref());
+        let mempool_txns_count = mempool_txns.len();
+
+        analysis_tasks.spawn(async move {
+            let fetched_mempool = FetchedMempool {
+                mempool_txns,
+                mempool_time_ms,
+                target_block_height: snapshot_target_height
+            };
+
+            let analysis = run_analysis(config_clone.clone(), run_dir_clone.clone(), run_id_clone.clone(), snapshots_clone, chain_source_clone, fetched_mempool, block_sighting);
+
+            match analysis.await {
+                //compared once the snapshots lock is released, so the reference fetches hold up no other analysis
+                Ok(Some(snapshot)) if config_clone.compares_every_snapshot() => {
+                    if let Err(e) = compare_snapshot(config_clone, run_dir_clone, run_id_clone, snapshot).await {
+                        eprintln!("Could not save template comparison: {}", e);
+                    }
+                }
+                Ok(_) => {}
+                Err(e) => eprintln!("Error in run_analysis: {}", e),
             }
         });
 
-        let last_snapshot_main_thread_clone = last_snapshot.clone();
-        let last_snapshot_main_thread_clone_mut = last_snapshot_main_thread_clone.lock().await;
+        let (latest_snapshot, blocks_found_count) = {
+            let snapshots = snapshots.lock().await;
+            (snapshots.latest(), snapshots.blocks_found_count)
+        };
 
-        let config_main_thread_clone = config.clone();
+        if let Some(dashboard) = dashboard.as_mut() {
+            dashboard.draw(&latest_snapshot)?;
 
-        println!(
-            "last_snapshot target & timestamp: {:?} & {}",
-            last_snapshot_main_thread_clone_mut.target_block_height, 
-            last_snapshot_main_thread_clone_mut.analyzer_result.snapshot_timestamp
-        );
+            if dashboard.quit_requested()? {
+                run_status = RunStatus::Partial;
+                break;
+            }
+        } else {
+            println!(
+                "last_snapshot target & timestamp: {:?} & {} | mempool: {} txns (+{} -{})",
+                latest_snapshot.target_block_height, 
+                latest_snapshot.analyzer_result().snapshot_timestamp,
+                mempool_txns_count,
+                mempool_delta.added.len(),
+                mempool_delta.removed.len()
+            );
+        }
         
-        if last_snapshot_main_thread_clone_mut.analyzer_result.blocks_found_count >= config_main_thread_clone.duration {
-            println!("Analysis Duration Reached. Saving Final Result in CSV Format And Exiting...");
-            let analyzer_result = AnalyzerResult::default();
-            let intermediate_result = analyzer_result.load_intermediate_result()?;
-            analyzer_result.save_final_result(intermediate_result);
+        if blocks_found_count >= config.duration {
             break;
         }
     }   
 
+    //leave the dashboard before printing the final messages
+    drop(dashboard);
+
+    match run_status {
+        RunStatus::Complete => println!("Analysis Duration Reached. Saving Final Result in CSV Format And Exiting..."),
+        RunStatus::Partial => println!("Shutdown Requested. Waiting For In-Flight Analysis And Saving Partial Result..."),
+    }
+
+    //in-flight tasks may still be scoring a found block and writing it to the result store
+    while let Some(task_result) = analysis_tasks.join_next().await {
+        if let Err(e) = task_result {
+            eprintln!("Analysis task failed: {}", e);
+        }
+    }
+
+    while comparison_tasks.join_next().await.is_some() {}
+
+    result_store.save_final_result(run_status).expect("Could not save final result");
+
     Ok(())
 }
\ No newline at end of file
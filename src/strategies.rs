@@ -1,12 +1,17 @@
 pub mod block_template_median;
+use crate::block_data::BlockTemplate;
+use crate::fee_rate::FeeRate;
 use crate::mempool_data::MempoolTransaction;
 use crate::strategies::block_template_median::BlockTemplateMedianEstimator;
 
+//block_template is what BlockBuilder builds from mempool_data, built once and shared by every strategy
 pub trait FeeRateEstimator {
-    fn estimate_fee_rate(&self, mempool_data: &Vec<MempoolTransaction>) -> f64;
+    fn estimate_fee_rate(&self, mempool_data: &[MempoolTransaction], block_template: &BlockTemplate) -> FeeRate;
     fn name(&self) -> &'static str;
 }
 
+pub const STRATEGY_NAMES: &[&str] = &["block_template_median"];
+
 pub fn select_strategy(strategy_name: &str) -> Box<dyn FeeRateEstimator> {
     match strategy_name {
         "block_template_median" => Box::new(BlockTemplateMedianEstimator),
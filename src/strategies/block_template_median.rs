@@ -1,6 +1,7 @@
 #![allow(unused)]
 use std::error::Error;
 use crate::{block_data, mempool_data};
+use crate::fee_rate::FeeRate;
 use crate::strategies::FeeRateEstimator;
 
 #[derive(Debug)]
@@ -8,30 +9,55 @@ pub struct BlockTemplateMedianEstimator;
 
 impl FeeRateEstimator for BlockTemplateMedianEstimator {
 
-    fn estimate_fee_rate(&self, mempool_data: &Vec<mempool_data::MempoolTransaction>) -> f64 {
-        
-        //build block template
-        let block_template = block_data::BlockBuilder::build_block(mempool_data).expect("Could not get block template");
+    fn estimate_fee_rate(&self, mempool_data: &[mempool_data::MempoolTransaction], block_template: &block_data::BlockTemplate) -> FeeRate {
+        let block_template = &block_template.txns;
 
-        let half_block_template_length = block_template.len() as f64 / 2.0;
-        let half_block_template_length_is_whole_number = (half_block_template_length.fract() == 0.0) || half_block_template_length.is_nan();
+        //no median without txns, as for a percentile of an empty mempool
+        if block_template.is_empty() {
+            return FeeRate::from_sat_per_vb(f64::NAN);
+        }
 
-        let mut median_fee_rate: f64 = 0.0;
+        let half_block_template_length = block_template.len() / 2;
 
-        if half_block_template_length_is_whole_number {
-            let median_index_1 = half_block_template_length.trunc() as usize;
-            let median_index_2 = half_block_template_length.trunc() as usize + 1;
+        if block_template.len().is_multiple_of(2) {
+            let median_index_1 = half_block_template_length - 1;
+            let median_index_2 = half_block_template_length;
 
-            median_fee_rate = (block_template[median_index_1].fee_rate + block_template[median_index_2].fee_rate) / 2.0;
+            FeeRate::from_sat_per_vb((block_template[median_index_1].fee_rate.sat_per_vb() + block_template[median_index_2].fee_rate.sat_per_vb()) / 2.0)
         } else {
-            let median_index = half_block_template_length.trunc() as usize + 1;
-            median_fee_rate = block_template[median_index].fee_rate;
+            block_template[half_block_template_length].fee_rate
         }
-
-        median_fee_rate
     }
 
     fn name(&self) -> &'static str {
         "Block Template Median Strategy"
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mempool_data::MempoolTransaction;
+
+    fn estimate(fees: &[u64]) -> FeeRate {
+        let mempool_txns: Vec<MempoolTransaction> = fees.iter().enumerate()
+            .map(|(i, fee)| MempoolTransaction::for_test(&format!("{:064x}", i), *fee, 100, &[]))
+            .collect();
+
+        let block_template = block_data::BlockBuilder::build_block(&mempool_txns).unwrap();
+        BlockTemplateMedianEstimator.estimate_fee_rate(&mempool_txns, &block_template)
+    }
+
+    #[test]
+    fn median_of_empty_template_is_nan() {
+        assert!(estimate(&[]).sat_per_vb().is_nan());
+    }
+
+    #[test]
+    fn median_of_small_templates() {
+        assert_eq!(estimate(&[500]).sat_per_vb(), 5.0);
+        assert_eq!(estimate(&[500, 1000]).sat_per_vb(), 7.5);
+        assert_eq!(estimate(&[500, 1000, 2000]).sat_per_vb(), 10.0);
+        assert_eq!(estimate(&[500, 1000, 2000, 4000]).sat_per_vb(), 15.0);
+    }
+}
@@ -0,0 +1,103 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::error::Error;
+use crate::block_data::{BlockMonitor, TemplateTransaction};
+use crate::mempool_data::MempoolTransaction;
+
+//our BlockBuilder template against core's getblocktemplate for the same snapshot. differences are ours minus core's
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct TemplateDiff {
+    pub our_txns_count: usize,
+    pub core_txns_count: usize,
+    pub common_txns_count: usize,
+    //shared txids over all txids in either template
+    pub jaccard_overlap: f64,
+    pub our_fees: u64,
+    pub core_fees: u64,
+    pub fee_difference: i64,
+    pub our_weight: u64,
+    pub core_weight: u64,
+    pub weight_difference: i64,
+    pub only_in_ours: Vec<String>,
+    pub only_in_core: Vec<String>,
+}
+
+impl TemplateDiff {
+    //getblocktemplate is asked when the comparison runs, a tick or so after the snapshot's mempool was
+    //fetched, so a few txns can differ only because they arrived or left in between
+    pub fn fetch(template_txns: &[MempoolTransaction]) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let core_template_txns = BlockMonitor::get_block_template().map_err(|e| e.to_string())?;
+
+        Ok(Self::compare(template_txns, &core_template_txns))
+    }
+
+    pub fn compare(template_txns: &[MempoolTransaction], core_template_txns: &[TemplateTransaction]) -> Self {
+        let our_txids: HashSet<&String> = template_txns.iter().map(|txn| &txn.txid).collect();
+        let core_txids: HashSet<&String> = core_template_txns.iter().map(|txn| &txn.txid).collect();
+
+        let common_txns_count = our_txids.intersection(&core_txids).count();
+        let all_txns_count = our_txids.union(&core_txids).count();
+
+        let our_fees: u64 = template_txns.iter().map(|txn| txn.fee).sum();
+        let core_fees: u64 = core_template_txns.iter().map(|txn| txn.fee).sum();
+        let our_weight: u64 = template_txns.iter().map(|txn| txn.weight).sum();
+        let core_weight: u64 = core_template_txns.iter().map(|txn| txn.weight).sum();
+
+        TemplateDiff {
+            our_txns_count: template_txns.len(),
+            core_txns_count: core_template_txns.len(),
+            common_txns_count,
+            //two empty templates agree completely
+            jaccard_overlap: if all_txns_count == 0 { 1.0 } else { common_txns_count as f64 / all_txns_count as f64 },
+            our_fees,
+            core_fees,
+            fee_difference: our_fees as i64 - core_fees as i64,
+            our_weight,
+            core_weight,
+            weight_difference: our_weight as i64 - core_weight as i64,
+            only_in_ours: template_txns.iter().filter(|txn| !core_txids.contains(&txn.txid)).map(|txn| txn.txid.clone()).collect(),
+            only_in_core: core_template_txns.iter().filter(|txn| !our_txids.contains(&txn.txid)).map(|txn| txn.txid.clone()).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn core_txn(txid: &str, fee: u64, weight: u64) -> TemplateTransaction {
+        TemplateTransaction { txid: txid.to_string(), fee, weight }
+    }
+
+    #[test]
+    fn overlap_and_differences_are_ours_against_core() {
+        let our_txns = vec![
+            MempoolTransaction::for_test("shared", 1000, 100, &[]),
+            MempoolTransaction::for_test("ours", 500, 200, &[]),
+        ];
+        let core_txns = vec![core_txn("shared", 1000, 400), core_txn("core-a", 2000, 600), core_txn("core-b", 300, 200)];
+
+        let diff = TemplateDiff::compare(&our_txns, &core_txns);
+
+        assert_eq!((diff.our_txns_count, diff.core_txns_count, diff.common_txns_count), (2, 3, 1));
+        assert_eq!(diff.jaccard_overlap, 0.25);
+        assert_eq!((diff.our_fees, diff.core_fees, diff.fee_difference), (1500, 3300, -1800));
+        assert_eq!((diff.our_weight, diff.core_weight, diff.weight_difference), (1200, 1200, 0));
+        assert_eq!(diff.only_in_ours, vec!["ours"]);
+        assert_eq!(diff.only_in_core, vec!["core-a", "core-b"]);
+
+        //positive when our template takes more
+        let smaller_core = TemplateDiff::compare(&our_txns, &core_txns[..1]);
+        assert_eq!(smaller_core.jaccard_overlap, 0.5);
+        assert_eq!((smaller_core.fee_difference, smaller_core.weight_difference), (500, 800));
+        assert!(smaller_core.only_in_core.is_empty());
+    }
+
+    #[test]
+    fn two_empty_templates_agree_completely() {
+        let diff = TemplateDiff::compare(&[], &[]);
+
+        assert_eq!(diff.jaccard_overlap, 1.0);
+        assert_eq!((diff.fee_difference, diff.weight_difference), (0, 0));
+    }
+}
@@ -0,0 +1,60 @@
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+//a local http server answering GETs with canned json by path, standing in for esplora and mempool.space
+pub struct TestServer {
+    pub base_url: String,
+    routes: Arc<Mutex<HashMap<String, String>>>,
+    requests: Arc<Mutex<Vec<String>>>,
+}
+
+impl TestServer {
+    pub fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Error binding test server");
+        let base_url = format!("http://{}", listener.local_addr().expect("Error getting test server address"));
+        let routes: Arc<Mutex<HashMap<String, String>>> = Arc::default();
+        let requests: Arc<Mutex<Vec<String>>> = Arc::default();
+
+        let (server_routes, server_requests) = (routes.clone(), requests.clone());
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(mut stream) = stream else { continue };
+                let mut reader = BufReader::new(stream.try_clone().expect("Error cloning test server stream"));
+
+                let mut request_line = String::new();
+                if reader.read_line(&mut request_line).is_err() {
+                    continue;
+                }
+                //the headers are read and ignored
+                let mut header = String::new();
+                while reader.read_line(&mut header).is_ok_and(|read| read > 2) {
+                    header.clear();
+                }
+
+                let path = request_line.split_whitespace().nth(1).unwrap_or("/").to_string();
+                let body = server_routes.lock().expect("Test server routes lock poisoned").get(&path).cloned();
+                server_requests.lock().expect("Test server requests lock poisoned").push(path);
+
+                let response = match body {
+                    Some(body) => format!("HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", body.len(), body),
+                    None => "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string(),
+                };
+                let _ = stream.write_all(response.as_bytes());
+            }
+        });
+
+        TestServer { base_url, routes, requests }
+    }
+
+    pub fn set(&self, path: &str, body: impl Into<String>) {
+        self.routes.lock().expect("Test server routes lock poisoned").insert(path.to_string(), body.into());
+    }
+
+    //paths requested so far, in order
+    pub fn requests(&self) -> Vec<String> {
+        self.requests.lock().expect("Test server requests lock poisoned").clone()
+    }
+}
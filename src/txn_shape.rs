@@ -0,0 +1,227 @@
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use crate::mempool_data::MempoolTransaction;
+use crate::runner::bcli;
+
+//OP_FALSE OP_IF PUSH3 "ord", the envelope ordinals inscriptions are written in
+const INSCRIPTION_ENVELOPE: &str = "0063036f7264";
+//OP_CHECKMULTISIG, the last opcode of a multisig redeem or witness script
+const OP_CHECKMULTISIG: &str = "ae";
+
+#[derive(ValueEnum, Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum ScriptType {
+    P2pk,
+    P2pkh,
+    P2sh,
+    P2shP2wpkh,
+    P2shP2wsh,
+    P2wpkh,
+    P2wsh,
+    P2tr,
+    Multisig,
+    OpReturn,
+    Unknown,
+    //the transaction's inputs (or outputs) are of more than one type
+    Mixed,
+}
+
+impl ScriptType {
+    pub fn label(&self) -> &'static str {
+        match self {
+            ScriptType::P2pk => "p2pk",
+            ScriptType::P2pkh => "p2pkh",
+            ScriptType::P2sh => "p2sh",
+            ScriptType::P2shP2wpkh => "p2sh-p2wpkh",
+            ScriptType::P2shP2wsh => "p2sh-p2wsh",
+            ScriptType::P2wpkh => "p2wpkh",
+            ScriptType::P2wsh => "p2wsh",
+            ScriptType::P2tr => "p2tr",
+            ScriptType::Multisig => "multisig",
+            ScriptType::OpReturn => "op-return",
+            ScriptType::Unknown => "unknown",
+            ScriptType::Mixed => "mixed",
+        }
+    }
+
+    //scriptPubKey types as reported by bitcoind
+    fn from_script_pub_key_type(script_type: &str) -> Self {
+        match script_type {
+            "pubkey" => ScriptType::P2pk,
+            "pubkeyhash" => ScriptType::P2pkh,
+            "scripthash" => ScriptType::P2sh,
+            "witness_v0_keyhash" => ScriptType::P2wpkh,
+            "witness_v0_scripthash" => ScriptType::P2wsh,
+            "witness_v1_taproot" => ScriptType::P2tr,
+            "multisig" => ScriptType::Multisig,
+            "nulldata" => ScriptType::OpReturn,
+            _ => ScriptType::Unknown,
+        }
+    }
+
+    //a single type if all are the same, mixed otherwise
+    fn summarize(script_types: &[ScriptType]) -> Self {
+        match script_types.first() {
+            Some(first) if script_types.iter().all(|t| t == first) => *first,
+            Some(_) => ScriptType::Mixed,
+            None => ScriptType::Unknown,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct ScriptPubKey {
+    #[serde(rename = "type")]
+    script_type: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct Prevout {
+    #[serde(rename = "scriptPubKey")]
+    script_pub_key: ScriptPubKey,
+}
+
+#[derive(Deserialize, Debug)]
+struct ScriptSig {
+    asm: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct DecodedInput {
+    txid: Option<String>,
+    vout: Option<u32>,
+    #[serde(rename = "scriptSig")]
+    script_sig: Option<ScriptSig>,
+    txinwitness: Option<Vec<String>>,
+    prevout: Option<Prevout>,
+}
+
+#[derive(Deserialize, Debug)]
+struct DecodedOutput {
+    #[serde(rename = "scriptPubKey")]
+    script_pub_key: ScriptPubKey,
+}
+
+#[derive(Deserialize, Debug)]
+struct DecodedTransaction {
+    vin: Vec<DecodedInput>,
+    vout: Vec<DecodedOutput>,
+}
+
+impl DecodedInput {
+    //needs the prevout, which getrawtransaction only reports at verbosity 2
+    fn script_type(&self) -> ScriptType {
+        let witness = self.txinwitness.as_deref().unwrap_or_default();
+        let prevout_type = match &self.prevout {
+            Some(prevout) => ScriptType::from_script_pub_key_type(&prevout.script_pub_key.script_type),
+            None => return ScriptType::Unknown,
+        };
+
+        match prevout_type {
+            //a p2sh input with a witness spends a wrapped segwit output
+            ScriptType::P2sh if !witness.is_empty() => {
+                if Self::ends_in_multisig(witness.last()) {
+                    ScriptType::Multisig
+                } else if witness.len() == 2 && witness[1].len() == 66 {
+                    ScriptType::P2shP2wpkh
+                } else {
+                    ScriptType::P2shP2wsh
+                }
+            }
+            ScriptType::P2sh => {
+                let redeem_script = self.script_sig.as_ref().and_then(|s| s.asm.split(' ').next_back().map(str::to_string));
+                if Self::ends_in_multisig(redeem_script.as_ref()) { ScriptType::Multisig } else { ScriptType::P2sh }
+            }
+            ScriptType::P2wsh if Self::ends_in_multisig(witness.last()) => ScriptType::Multisig,
+            script_type => script_type,
+        }
+    }
+
+    fn ends_in_multisig(script: Option<&String>) -> bool {
+        script.is_some_and(|s| s.ends_with(OP_CHECKMULTISIG))
+    }
+
+    fn has_inscription(&self) -> bool {
+        self.txinwitness.iter().flatten().any(|item| item.contains(INSCRIPTION_ENVELOPE))
+    }
+}
+
+//what a transaction spends and creates, which getrawmempool does not report
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TxnShape {
+    pub input_script_types: Vec<ScriptType>,
+    pub output_script_types: Vec<ScriptType>,
+    pub has_op_return: bool,
+    pub has_inscription: bool,
+    //"txid:vout" of each spent output, used to spot replacements that conflict with the transaction
+    #[serde(default)]
+    pub spent_outpoints: Vec<String>,
+}
+
+impl TxnShape {
+    pub fn fetch(txid: &str) -> Option<Self> {
+        let raw_txn = bcli(&format!("getrawtransaction {} 2", txid)).ok()?;
+        let decoded_txn: DecodedTransaction = serde_json::from_slice(&raw_txn).ok()?;
+
+        let output_script_types: Vec<ScriptType> = decoded_txn.vout.iter()
+            .map(|output| ScriptType::from_script_pub_key_type(&output.script_pub_key.script_type))
+            .collect();
+
+        Some(TxnShape {
+            input_script_types: decoded_txn.vin.iter().map(|input| input.script_type()).collect(),
+            has_op_return: output_script_types.contains(&ScriptType::OpReturn),
+            output_script_types,
+            has_inscription: decoded_txn.vin.iter().any(|input| input.has_inscription()),
+            spent_outpoints: decoded_txn.vin.iter()
+                .filter_map(|input| Some(format!("{}:{}", input.txid.as_ref()?, input.vout?)))
+                .collect(),
+        })
+    }
+
+    pub fn input_script_type(&self) -> ScriptType {
+        ScriptType::summarize(&self.input_script_types)
+    }
+
+    //op_return outputs are left out so a payment with a data carrier is not reported as mixed
+    pub fn output_script_type(&self) -> ScriptType {
+        let script_types: Vec<ScriptType> = self.output_script_types.iter()
+            .copied()
+            .filter(|t| *t != ScriptType::OpReturn)
+            .collect();
+
+        if script_types.is_empty() && self.has_op_return {
+            ScriptType::OpReturn
+        } else {
+            ScriptType::summarize(&script_types)
+        }
+    }
+}
+
+//decoding spawns a bitcoin-cli call per transaction, so shapes are kept for as long as the
+//transaction stays in the mempool and only newly seen transactions are decoded on each fetch
+#[derive(Clone, Default)]
+pub struct TxnShapeCache {
+    shapes: Arc<Mutex<HashMap<String, TxnShape>>>,
+}
+
+impl TxnShapeCache {
+    pub fn decode(&self, mempool_txns: &mut [MempoolTransaction]) {
+        let mut shapes = self.shapes.lock().expect("Txn shape cache lock poisoned");
+
+        let mempool_txids: HashSet<&String> = mempool_txns.iter().map(|txn| &txn.txid).collect();
+        shapes.retain(|txid, _| mempool_txids.contains(txid));
+
+        for txn in mempool_txns.iter_mut() {
+            if !shapes.contains_key(&txn.txid) {
+                //the transaction may have left the mempool since getrawmempool was called
+                match TxnShape::fetch(&txn.txid) {
+                    Some(shape) => { shapes.insert(txn.txid.clone(), shape); }
+                    None => continue,
+                }
+            }
+
+            txn.set_shape(shapes[&txn.txid].clone());
+        }
+    }
+}
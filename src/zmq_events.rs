@@ -0,0 +1,255 @@
+use sha2::{Digest, Sha256};
+use std::error::Error;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
+use zeromq::{Socket, SocketRecv, SubSocket};
+
+pub const ZMQ_TOPICS: [&str; 3] = ["hashblock", "rawtx", "sequence"];
+
+//what bitcoind publishes over zmq, reduced to the hashes the analyzer acts on
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChainEvent {
+    BlockConnected(String),
+    BlockDisconnected(String),
+    //accepted to or removed from the mempool, as reported on sequence
+    TxAdded(String),
+    TxRemoved(String),
+    //relayed or mined, as reported on rawtx. only acted on when the node does not publish sequence
+    TxSeen(String),
+}
+
+impl ChainEvent {
+    pub fn is_from_sequence(&self) -> bool {
+        !matches!(self, ChainEvent::TxSeen(_))
+    }
+}
+
+//bitcoind publishes [topic, body, 4 byte sequence number] and writes hashes in the byte order
+//the rpc shows them in, so they can be hex encoded as they are
+pub fn parse_zmq_message(topic: &[u8], body: &[u8]) -> Option<ChainEvent> {
+    match topic {
+        b"hashblock" if body.len() == 32 => Some(ChainEvent::BlockConnected(hex::encode(body))),
+        b"rawtx" => txid_of_raw_txn(body).map(ChainEvent::TxSeen),
+        //hash, then a label, then for mempool events the 8 byte mempool sequence
+        b"sequence" if body.len() >= 33 => {
+            let hash = hex::encode(&body[..32]);
+            match body[32] {
+                b'C' => Some(ChainEvent::BlockConnected(hash)),
+                b'D' => Some(ChainEvent::BlockDisconnected(hash)),
+                b'A' => Some(ChainEvent::TxAdded(hash)),
+                b'R' => Some(ChainEvent::TxRemoved(hash)),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+//double sha256 of the transaction without its witness, reversed into rpc byte order
+fn txid_of_raw_txn(raw_txn: &[u8]) -> Option<String> {
+    let stripped_txn = strip_witness(raw_txn)?;
+    let mut txid: Vec<u8> = Sha256::digest(Sha256::digest(&stripped_txn)).to_vec();
+    txid.reverse();
+
+    Some(hex::encode(txid))
+}
+
+fn strip_witness(raw_txn: &[u8]) -> Option<Vec<u8>> {
+    let mut cursor = 4;
+    //a zero input count followed by a non-zero flag marks the segwit serialization
+    let is_segwit = *raw_txn.get(4)? == 0 && *raw_txn.get(5)? != 0;
+    if is_segwit {
+        cursor += 2;
+    }
+
+    let inputs_start = cursor;
+    let inputs_count = read_compact_size(raw_txn, &mut cursor)?;
+    for _ in 0..inputs_count {
+        //previous outpoint, then the script sig, then the sequence
+        cursor += 36;
+        let script_len = read_compact_size(raw_txn, &mut cursor)?;
+        cursor += script_len as usize + 4;
+    }
+
+    let outputs_count = read_compact_size(raw_txn, &mut cursor)?;
+    for _ in 0..outputs_count {
+        //value, then the script pubkey
+        cursor += 8;
+        let script_len = read_compact_size(raw_txn, &mut cursor)?;
+        cursor += script_len as usize;
+    }
+    let outputs_end = cursor;
+
+    if outputs_end + 4 > raw_txn.len() {
+        return None;
+    }
+
+    let mut stripped_txn = raw_txn[..4].to_vec();
+    stripped_txn.extend_from_slice(&raw_txn[inputs_start..outputs_end]);
+    //the lock time is always the last 4 bytes, after any witness data
+    stripped_txn.extend_from_slice(&raw_txn[raw_txn.len() - 4..]);
+
+    Some(stripped_txn)
+}
+
+fn read_compact_size(bytes: &[u8], cursor: &mut usize) -> Option<u64> {
+    let prefix = *bytes.get(*cursor)?;
+    *cursor += 1;
+
+    let width = match prefix {
+        0xfd => 2,
+        0xfe => 4,
+        0xff => 8,
+        _ => return Some(prefix as u64),
+    };
+
+    let value_bytes = bytes.get(*cursor..*cursor + width)?;
+    *cursor += width;
+
+    Some(value_bytes.iter().rev().fold(0, |value, byte| (value << 8) | *byte as u64))
+}
+
+//connects to every endpoint and forwards the parsed events until the socket fails or the
+//receiver is dropped. any zmq publisher sending bitcoind's message format can stand in for the node
+pub async fn subscribe(endpoints: &[String]) -> Result<UnboundedReceiver<ChainEvent>, Box<dyn Error>> {
+    let mut socket = SubSocket::new();
+
+    for endpoint in endpoints {
+        socket.connect(endpoint).await?;
+    }
+
+    for topic in ZMQ_TOPICS {
+        socket.subscribe(topic).await?;
+    }
+
+    let (sender, receiver) = unbounded_channel();
+
+    tokio::spawn(async move {
+        loop {
+            let message = match socket.recv().await {
+                Ok(message) => message,
+                Err(e) => {
+                    eprintln!("ZMQ subscription failed: {}", e);
+                    break;
+                }
+            };
+
+            let (Some(topic), Some(body)) = (message.get(0), message.get(1)) else {
+                continue;
+            };
+
+            if let Some(event) = parse_zmq_message(topic, body) {
+                if sender.send(event).is_err() {
+                    break;
+                }
+            }
+        }
+    });
+
+    Ok(receiver)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::time::{timeout, Duration};
+    use zeromq::{PubSocket, SocketSend, ZmqMessage};
+
+    //the genesis block coinbase, which has no witness
+    const GENESIS_COINBASE: &str = "01000000010000000000000000000000000000000000000000000000000000000000000000ffffffff4d04ffff001d0104455468652054696d65732030332f4a616e2f32303039204368616e63656c6c6f72206f6e206272696e6b206f66207365636f6e64206261696c6f757420666f722062616e6b73ffffffff0100f2052a01000000434104678afdb0fe5548271967f1a67130b7105cd6a828e03909a67962e0ea1f61deb649f6bc3f4cef38c4f35504e51ec112de5c384df7ba0b8d578a4c702b6bf11d5fac00000000";
+    const GENESIS_COINBASE_TXID: &str = "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b";
+
+    //the same transaction in the segwit serialization, with one 32 byte witness item
+    fn genesis_coinbase_with_witness() -> Vec<u8> {
+        let raw_txn = hex::decode(GENESIS_COINBASE).unwrap();
+        let mut segwit_txn = raw_txn[..4].to_vec();
+        segwit_txn.extend_from_slice(&[0x00, 0x01]);
+        segwit_txn.extend_from_slice(&raw_txn[4..raw_txn.len() - 4]);
+        segwit_txn.extend_from_slice(&[0x01, 0x20]);
+        segwit_txn.extend_from_slice(&[0; 32]);
+        segwit_txn.extend_from_slice(&raw_txn[raw_txn.len() - 4..]);
+        segwit_txn
+    }
+
+    fn zmq_message(topic: &str, body: Vec<u8>) -> ZmqMessage {
+        let mut message = ZmqMessage::from(topic);
+        message.push_back(body.into());
+        message.push_back(vec![0u8; 4].into());
+        message
+    }
+
+    #[test]
+    fn strips_the_witness() {
+        let raw_txn = hex::decode(GENESIS_COINBASE).unwrap();
+
+        assert_eq!(strip_witness(&raw_txn), Some(raw_txn.clone()));
+        assert_eq!(strip_witness(&genesis_coinbase_with_witness()), Some(raw_txn.clone()));
+        assert_eq!(strip_witness(&raw_txn[..raw_txn.len() - 10]), None);
+    }
+
+    #[test]
+    fn parses_bitcoind_messages() {
+        let hash = [0xab; 32];
+
+        assert_eq!(parse_zmq_message(b"hashblock", &hash), Some(ChainEvent::BlockConnected("ab".repeat(32))));
+        assert_eq!(parse_zmq_message(b"hashblock", &hash[..31]), None);
+        assert_eq!(parse_zmq_message(b"rawtx", &genesis_coinbase_with_witness()), Some(ChainEvent::TxSeen(GENESIS_COINBASE_TXID.to_string())));
+
+        for (label, event) in [
+            (b'C', ChainEvent::BlockConnected("ab".repeat(32))),
+            (b'D', ChainEvent::BlockDisconnected("ab".repeat(32))),
+            (b'A', ChainEvent::TxAdded("ab".repeat(32))),
+            (b'R', ChainEvent::TxRemoved("ab".repeat(32))),
+        ] {
+            let mut body = hash.to_vec();
+            body.push(label);
+            body.extend_from_slice(&[0; 8]);
+            assert_eq!(parse_zmq_message(b"sequence", &body), Some(event));
+        }
+
+        assert_eq!(parse_zmq_message(b"sequence", &hash), None);
+        assert_eq!(parse_zmq_message(b"hashtx", &hash), None);
+    }
+
+    //a pub socket standing in for bitcoind
+    #[tokio::test]
+    async fn receives_events_from_a_publisher() {
+        let mut publisher = PubSocket::new();
+        let endpoint = publisher.bind("tcp://127.0.0.1:0").await.unwrap();
+        let mut events = subscribe(&[endpoint.to_string()]).await.unwrap();
+
+        let mut tx_added = [0xcd; 32].to_vec();
+        tx_added.push(b'A');
+        tx_added.extend_from_slice(&[0; 8]);
+
+        //subscriptions reach the publisher some time after connecting, and until then messages are dropped.
+        //sequence is subscribed to last, so once one arrives every topic is
+        let first_event = timeout(Duration::from_secs(10), async {
+            loop {
+                publisher.send(zmq_message("sequence", tx_added.clone())).await.unwrap();
+                tokio::select! {
+                    event = events.recv() => break event,
+                    _ = tokio::time::sleep(Duration::from_millis(50)) => {}
+                }
+            }
+        }).await.unwrap();
+        assert_eq!(first_event, Some(ChainEvent::TxAdded("cd".repeat(32))));
+
+        publisher.send(zmq_message("hashblock", vec![0xef; 32])).await.unwrap();
+        publisher.send(zmq_message("rawtx", hex::decode(GENESIS_COINBASE).unwrap())).await.unwrap();
+
+        let mut received = vec![];
+        while received.len() < 2 {
+            match timeout(Duration::from_secs(10), events.recv()).await.unwrap() {
+                //probes sent before the first one arrived may still be in flight
+                Some(ChainEvent::TxAdded(_)) => {}
+                Some(event) => received.push(event),
+                None => panic!("subscription closed"),
+            }
+        }
+
+        assert_eq!(received, vec![
+            ChainEvent::BlockConnected("ef".repeat(32)),
+            ChainEvent::TxSeen(GENESIS_COINBASE_TXID.to_string()),
+        ]);
+    }
+}